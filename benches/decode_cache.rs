@@ -0,0 +1,98 @@
+//! Runs a loop-heavy program step by step, with the quiet `run` loop, and with the decoded instruction cache on top.
+//!
+//! ```text
+//! cargo bench --bench decode_cache
+//! ```
+
+use std::time::{Duration, Instant};
+
+use drama_isa::word::{encode, encode_without_operand};
+use drama_isa::{Condition, FunctionCode, Mode1, Mode2, Register};
+
+use drama_sim::state::cpu::CPU;
+use drama_sim::state::ram::RAM;
+
+const ROUNDS: usize = 5;
+
+/// Adds up `i * j` for every `i` and `j` below 1000, keeping the running total in memory.
+fn program() -> RAM {
+    let mut ram = RAM::new();
+    let program = [
+        encode(FunctionCode::HIA, Mode1::Value, Mode2::NoIndex, Register::R1, Register::R0, 0),
+        // outer:
+        encode(FunctionCode::HIA, Mode1::Value, Mode2::NoIndex, Register::R2, Register::R0, 0),
+        // inner:
+        encode(FunctionCode::HIA, Mode1::Value, Mode2::Index, Register::R3, Register::R1, 0),
+        encode(FunctionCode::VER, Mode1::Value, Mode2::Index, Register::R3, Register::R2, 0),
+        encode(FunctionCode::OPT, Mode1::Direct, Mode2::NoIndex, Register::R3, Register::R0, 100),
+        encode(FunctionCode::BIG, Mode1::Address, Mode2::NoIndex, Register::R3, Register::R0, 100),
+        encode(FunctionCode::OPT, Mode1::Value, Mode2::NoIndex, Register::R2, Register::R0, 1),
+        encode(FunctionCode::VGL, Mode1::Value, Mode2::NoIndex, Register::R2, Register::R0, 1000),
+        encode(FunctionCode::VSP, Mode1::Address, Mode2::NoIndex, Condition::NEG.register(), Register::R0, 2),
+        encode(FunctionCode::OPT, Mode1::Value, Mode2::NoIndex, Register::R1, Register::R0, 1),
+        encode(FunctionCode::VGL, Mode1::Value, Mode2::NoIndex, Register::R1, Register::R0, 1000),
+        encode(FunctionCode::VSP, Mode1::Address, Mode2::NoIndex, Condition::NEG.register(), Register::R0, 1),
+        encode_without_operand(FunctionCode::STP),
+    ];
+    for (address, &word) in program.iter().enumerate() {
+        ram[address] = word;
+        ram.mark_loaded(address);
+    }
+    ram
+}
+
+/// How the program is run.
+#[derive(Clone, Copy)]
+enum Engine {
+    /// One [`CPU::step`] at a time, decoding every word again, as the debugger does.
+    Stepping,
+    /// [`CPU::run`], decoding every word again.
+    Running,
+    /// [`CPU::run`] with the decode cache.
+    Cached,
+}
+
+/// The fastest of a few runs, with the machine as that run left it.
+fn measure(engine: Engine) -> (Duration, CPU, RAM) {
+    let mut best = None;
+    for _ in 0..ROUNDS {
+        let mut cpu = CPU::new();
+        cpu.decode_cache = matches!(engine, Engine::Cached);
+        let mut ram = program();
+        let start = Instant::now();
+        match engine {
+            Engine::Stepping => {
+                while !cpu.stopped {
+                    cpu.step(&mut ram).expect("the benchmark program faulted");
+                }
+            }
+            Engine::Running | Engine::Cached => {
+                cpu.run(&mut ram).expect("the benchmark program faulted");
+            }
+        }
+        let elapsed = start.elapsed();
+        if best.as_ref().is_none_or(|(fastest, _, _)| elapsed < *fastest) {
+            best = Some((elapsed, cpu, ram));
+        }
+    }
+    best.unwrap()
+}
+
+fn main() {
+    let (stepping, cpu, ram) = measure(Engine::Stepping);
+    let rate = |elapsed: Duration| cpu.cycles as f64 / elapsed.as_secs_f64() / 1e6;
+    println!("{} instructions, best of {} runs", cpu.cycles, ROUNDS);
+    println!("{:<32} {:>9} {:>12} {:>8}", "", "time", "M insn/s", "speedup");
+    println!("{:<32} {:>9.1?} {:>12.1} {:>7.2}x", "step, decoding every fetch", stepping, rate(stepping), 1.0);
+    for (name, engine) in [("run, decoding every fetch", Engine::Running), ("run, decode cache", Engine::Cached)] {
+        let (elapsed, other_cpu, other_ram) = measure(engine);
+        // The engines must agree on everything
+        assert_eq!(cpu.accumulators, other_cpu.accumulators);
+        assert_eq!(cpu.registers_initialised, other_cpu.registers_initialised);
+        assert_eq!(cpu.condition_code, other_cpu.condition_code);
+        assert_eq!(cpu.overflow, other_cpu.overflow);
+        assert_eq!(cpu.cycles, other_cpu.cycles);
+        assert!((0..10_000usize).all(|address| ram[address] == other_ram[address]));
+        println!("{:<32} {:>9.1?} {:>12.1} {:>7.2}x", name, elapsed, rate(elapsed), stepping.as_secs_f64() / elapsed.as_secs_f64());
+    }
+}
@@ -19,12 +19,20 @@ pub enum CompilationError<'a> {
         line: Line<'a>,
         malformed_operand: String,
     },
+    NotACondition {
+        line: Line<'a>,
+        malformed_operand: String,
+    },
     UnexpectedInterpretation(Line<'a>, String),
     UnsupportedInterpretation(Line<'a>, String, Vec<char>),
     TooLongInterpretation(Line<'a>, String),
     NoSecondOperand(Line<'a>, String),
+    MalformedString(Line<'a>, &'static str),
     RegRegUnsupported(Line<'a>, String),
     RegRegInterpretation(Line<'a>, String),
+    UndefinedExport(Line<'a>, &'a str),
+    /// An imported label is used in a way the linker can't fill in.
+    Unrelocatable(Line<'a>),
     NoCompilation,
 }
 
@@ -36,12 +44,16 @@ impl CompilationError<'_> {
             CompilationError::NoOperand { line, .. } => Some(line),
             CompilationError::Incomprehensible(line, ..) => Some(line),
             CompilationError::NotARegister { line, .. } => Some(line),
+            CompilationError::NotACondition { line, .. } => Some(line),
             CompilationError::UnexpectedInterpretation(line, _) => Some(line),
             CompilationError::UnsupportedInterpretation(line, ..) => Some(line),
             CompilationError::TooLongInterpretation(line, ..) => Some(line),
             CompilationError::NoSecondOperand(line, ..) => Some(line),
+            CompilationError::MalformedString(line, _) => Some(line),
             CompilationError::RegRegUnsupported(line, ..) => Some(line),
             CompilationError::RegRegInterpretation(line, ..) => Some(line),
+            CompilationError::UndefinedExport(line, _) => Some(line),
+            CompilationError::Unrelocatable(line) => Some(line),
             CompilationError::NoCompilation => None
         }
     }
@@ -57,13 +69,17 @@ impl std::fmt::Display for CompilationError<'_> {
             CompilationError::NoCompilation => write!(f, "No compilation happened"),
             CompilationError::NoOperand { opcode, .. } => write!(f, "Instruction `{}` expects an operand, but you provided none", opcode),
             CompilationError::NotARegister { malformed_operand, .. } => write!(f, "`{}` is not in the form of Rx, where 0 <= x <= 9.", malformed_operand),
+            CompilationError::NotACondition { malformed_operand, .. } => write!(f, "`{}` is not a condition, expected one of NUL, NNEG, NPOS, POS, NEG or NNUL.", malformed_operand),
             CompilationError::UnexpectedInterpretation(_, opcode) => write!(f, "Instruction `{}` does not expect an interpretation", opcode),
             CompilationError::UnsupportedInterpretation(_, op, provides) => write!(f, "Instruction `{}` supports only interpretations {:?}", op, provides),
             CompilationError::TooLongInterpretation(_, int) => write!(f, "Interpretations consist of exactly one character, thus `{}` is invalid.", int),
             CompilationError::NoSecondOperand(_, opcode) => write!(f, "Instruction `{}` expects two operands, but you provided only one", opcode),
+            CompilationError::MalformedString(_, reason) => write!(f, "Malformed string literal: {}", reason),
             CompilationError::RegRegUnsupported(_, opcode) => write!(f, "Register-register operations are not supported for `{}`", opcode),
             CompilationError::RegRegInterpretation(_, opcode) => write!(f, "Register-register operations using `{}` don't support interpretations", opcode),
             CompilationError::Incomprehensible(..) => write!(f, "Not a valid instruction or integer expression"),
+            CompilationError::UndefinedExport(_, label) => write!(f, "`{}` is exported, but there is no such label", label),
+            CompilationError::Unrelocatable(_) => write!(f, "Imported labels may only be used as `label` or `label + number`"),
         }
     }
 }
\ No newline at end of file
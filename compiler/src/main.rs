@@ -1,20 +1,18 @@
-#![feature(try_trait)]
-#![feature(or_patterns)]
-#![feature(arbitrary_enum_discriminant)]
-#![feature(pattern)]
-
 use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::fmt::Debug;
+use std::io::Write;
 use mexprp::{Answer, EvalError, Context, Term};
 use crate::compilation_error::*;
-use std::str::pattern::Pattern;
-use std::ops::Try;
 use std::str::FromStr;
-use constants::*;
+use drama_isa::listing::read_listing;
+use drama_isa::{disassembler, linker};
+use drama_isa::object::{AssembledWord, ObjectFile, Relocation, RelocationKind};
+use drama_isa::source_map::SourceMap;
+use drama_isa::word::{self, encode, encode_without_operand};
+use drama_isa::{Condition, FunctionCode, Mode1, Mode2, Register};
 
 mod compilation_error;
-mod constants;
 
 #[derive(Debug, Clone, Copy)]
 pub struct Line<'a> {
@@ -23,54 +21,318 @@ pub struct Line<'a> {
     line: &'a str,
 }
 
+/// A program laid out in memory, before its lines are turned into words.
+struct Layout<'a> {
+    lines: Vec<Line<'a>>,
+    labels: HashMap<&'a str, usize>,
+    /// `(start, length)` of every `RESGR` region.
+    reserved: Vec<(usize, usize)>,
+    /// The labels named by `EXPORT`, with the line naming them.
+    exports: Vec<(Line<'a>, &'a str)>,
+    /// The labels named by `IMPORT`.
+    imports: Vec<&'a str>,
+}
+
+/// The words a program compiles to.
+struct Assembled {
+    words: Vec<(usize, isize)>,
+    /// `(address, line_number)` for every word.
+    lines: Vec<(usize, usize)>,
+    /// The words the linker has to adjust, in order of address.
+    relocations: Vec<Relocation>,
+    /// Whether every use of a label of the file can be adjusted by the linker.
+    relocatable: bool,
+}
+
+/// The labels expressions are evaluated with.
+struct EvaluationContext<'a> {
+    context: Context<f64>,
+    /// The labels whose address is only known once the program is linked, with the symbol the linker relocates
+    /// them by: `None` for the labels of the file, which move along with it, or the name of an import.
+    movable: HashMap<&'a str, Option<&'a str>>,
+    /// How many times each symbol of `movable` was added into the expressions evaluated since it was last
+    /// cleared, or `None` for a symbol used in any other way, as in `label * 2`.
+    uses: Vec<(Option<&'a str>, Option<isize>)>,
+}
+
+const USAGE: &str = "\
+Usage:
+    dasm FILE                   Assemble FILE and print every word as `address: word`
+    dasm --object FILE          Assemble FILE into a relocatable object file
+    dasm --binary-object FILE   The same, in the binary form
+    dasm --source-map FILE      Print the labels of FILE and the source line of every word
+    dasm --disassemble FILE     Turn a listing of `address: word` lines back into assembly
+    dasm --link FILE...         Assemble or read every FILE and link them into one object file
+    dasm --binary-link FILE...  The same, in the binary form
+";
+
 fn main() {
-    const INPUT: &str = include_str!("test_resgr");
-    match compile(INPUT) {
-        Ok(c) => {
-            println!("Compilation successful!");
-            println!();
-            for (address, value) in c.iter() {
-                println!("{:04}: {:010}", address, value)
+    let args: Vec<String> = std::env::args().collect();
+    if let [_, flag] = args.as_slice() {
+        if flag == "--help" || flag == "-h" {
+            print!("{}", USAGE);
+            return;
+        }
+    }
+    if let [_, path] = args.as_slice() {
+        if path.starts_with("--") {
+            fail(&format!("`{}` needs a file\n\n{}", path, USAGE));
+        }
+        let source = std::fs::read_to_string(path).unwrap_or_else(|e| fail(&format!("Could not read {}: {}", path, e)));
+        match compile(&source) {
+            Ok(c) => {
+                for (address, value) in c.iter() {
+                    println!("{:04}: {:010}", address, value)
+                }
+            }
+            Err(e) => {
+                eprintln!("Compilation error:");
+                eprintln!("{}", describe_error(&e));
+                std::process::exit(1);
             }
         }
-        Err(e) => {
-            println!("Compilation error:");
-            if let Some(line) = e.get_line() {
-                let line_str = line.line;
-                println!("\nOn line {} \t[address {}]", line.line_number, line.address);
-                println!("\t{}", line_str);
-                println!("\t{} {}", (0..line_str.len())
-                    .map(|i| if &line_str[i..=i] == "\t" { '\t' } else { '^' })
-                    .collect::<String>(), e)
-            } else {
-                println!("{}", e);
+        return;
+    }
+    if let [_, flag, paths @ ..] = args.as_slice() {
+        if flag == "--link" || flag == "--binary-link" {
+            let mut objects = Vec::new();
+            for path in paths {
+                let bytes = std::fs::read(path).unwrap_or_else(|e| fail(&format!("Could not read {}: {}", path, e)));
+                let object = if bytes.starts_with(drama_isa::object::BINARY_MAGIC) || bytes.starts_with(b"DRAMA object v") {
+                    ObjectFile::from_bytes(&bytes).unwrap_or_else(|e| {
+                        eprintln!("Invalid object file {}: {}", path, e);
+                        std::process::exit(1);
+                    })
+                } else {
+                    let source = String::from_utf8_lossy(&bytes);
+                    compile_to_object(&source).unwrap_or_else(|e| {
+                        eprintln!("Compilation error in {}:", path);
+                        eprintln!("{}", describe_error(&e));
+                        std::process::exit(1);
+                    })
+                };
+                objects.push((path.clone(), object));
+            }
+            match linker::link(&objects) {
+                Ok(object) if flag == "--link" => print!("{}", object),
+                Ok(object) => std::io::stdout().write_all(&object.to_bytes()).expect("Could not write the object file"),
+                Err(e) => {
+                    eprintln!("Link error: {}", e);
+                    std::process::exit(1);
+                }
             }
+            return;
+        }
+    }
+    if let [_, flag, path] = args.as_slice() {
+        if flag == "--disassemble" {
+            let listing = std::fs::read_to_string(path).unwrap_or_else(|e| fail(&format!("Could not read {}: {}", path, e)));
+            match read_listing(&listing) {
+                Ok(image) => print!("{}", disassembler::disassemble(&image)),
+                Err(e) => fail(&format!("Invalid memory image: {}", e)),
+            }
+            return;
+        }
+        if flag == "--object" || flag == "--binary-object" {
+            let source = std::fs::read_to_string(path).unwrap_or_else(|e| fail(&format!("Could not read {}: {}", path, e)));
+            match compile_to_object(&source) {
+                Ok(object) if flag == "--object" => print!("{}", object),
+                Ok(object) => std::io::stdout().write_all(&object.to_bytes()).expect("Could not write the object file"),
+                Err(e) => {
+                    eprintln!("Compilation error:");
+                    eprintln!("{}", describe_error(&e));
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        if flag == "--source-map" {
+            let source = std::fs::read_to_string(path).unwrap_or_else(|e| fail(&format!("Could not read {}: {}", path, e)));
+            match compile_with_source_map(&source) {
+                Ok((_, source_map)) => print!("{}", source_map),
+                Err(e) => fail(&format!("Compilation error:\n{}", describe_error(&e))),
+            }
+            return;
+        }
+    }
+
+    match args.get(1) {
+        Some(flag) if flag.starts_with("--") && !matches!(flag.as_str(), "--disassemble" | "--object" | "--binary-object" | "--source-map" | "--link" | "--binary-link") => {
+            fail(&format!("Unknown option `{}`\n\n{}", flag, USAGE))
+        }
+        _ => fail(USAGE),
+    }
+}
+
+/// Reports what went wrong on stderr and exits with status 1.
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1);
+}
+
+/// Points out the offending line under the error, if there is one.
+fn describe_error(e: &CompilationError) -> String {
+    match e.get_line() {
+        Some(line) => {
+            let line_str = line.line;
+            format!("\nOn line {} \t[address {}]\n\t{}\n\t{} {}", line.line_number, line.address, line_str, (0..line_str.len())
+                .map(|i| if &line_str[i..=i] == "\t" { '\t' } else { '^' })
+                .collect::<String>(), e)
         }
+        None => e.to_string(),
     }
 }
 
 fn compile(source_code: &str) -> Result<Box<[(usize, isize)]>, CompilationError> {
+    compile_with_source_map(source_code).map(|(image, _)| image)
+}
+
+/// Compiles like `compile`, also returning the source line each address was compiled from and the labels.
+fn compile_with_source_map(source_code: &str) -> Result<(Box<[(usize, isize)]>, SourceMap), CompilationError> {
     let filtered = as_filtered_lines(source_code);
-    let (expanded, labels) = expand_and_omit_labels(&filtered)?;
-    let evaluation_context = {
+    let Layout { lines, labels, .. } = expand_and_omit_labels(&filtered)?;
+    let mut evaluation_context = EvaluationContext::new(labels.iter().map(|(&label, &address)| (label, address)), HashMap::new());
+    let Assembled { words, lines, .. } = to_numerical_representation(lines, &mut evaluation_context)?;
+
+    let mut source_map = SourceMap::new();
+    for (address, line_number) in lines {
+        source_map.insert(address, line_number);
+    }
+    for (label, address) in labels {
+        source_map.insert_label(label, address);
+    }
+    Ok((words.into_boxed_slice(), source_map))
+}
+
+/// Compiles into a relocatable object file, which starts executing at address 0.
+///
+/// The imported labels are taken to be at address 0. Every word that adds a label of the file or an import into
+/// its value gets a relocation, recorded as its expression is evaluated. If the labels of the file are used in a
+/// way that can't be adjusted, such as `label * 2`, the object can only be placed at address 0.
+fn compile_to_object(source_code: &str) -> Result<ObjectFile, CompilationError> {
+    let filtered = as_filtered_lines(source_code);
+    let layout = expand_and_omit_labels(&filtered)?;
+    if let Some(&(line, export)) = layout.exports.iter().find(|(_, export)| !layout.labels.contains_key(export)) {
+        return Err(CompilationError::UndefinedExport(line, export));
+    }
+    let movable = layout.imports.iter().map(|&import| (import, Some(import)))
+        .chain(layout.labels.keys().map(|&label| (label, None)))
+        .collect();
+    let mut evaluation_context = EvaluationContext::new(
+        layout.imports.iter().map(|&import| (import, 0))
+            .chain(layout.labels.iter().map(|(&label, &address)| (label, address))),
+        movable,
+    );
+    let Assembled { words, lines, relocations, relocatable } = to_numerical_representation(layout.lines, &mut evaluation_context)?;
+
+    let words = words.iter().zip(lines.iter())
+        .map(|(&(address, value), &(_, line_number))| AssembledWord { address, value, line: Some(line_number) })
+        .collect();
+    let mut symbols: Vec<(String, usize)> = layout.labels.iter().map(|(&label, &address)| (label.to_string(), address)).collect();
+    symbols.sort_by(|(a, a_address), (b, b_address)| (a_address, a).cmp(&(b_address, b)));
+    Ok(ObjectFile {
+        entry_point: 0,
+        words,
+        symbols,
+        reserved: layout.reserved,
+        relocatable,
+        exports: layout.exports.iter().map(|&(_, export)| export.to_string()).collect(),
+        imports: layout.imports.iter().map(|import| import.to_string()).collect(),
+        relocations,
+        sections: Vec::new(),
+    })
+}
+
+impl<'a> EvaluationContext<'a> {
+    /// A context with the value of every label, of which those in `movable` are recorded in `uses`.
+    fn new<I: IntoIterator<Item = (&'a str, usize)>>(labels: I, movable: HashMap<&'a str, Option<&'a str>>) -> Self {
         let mut context = Context::new();
-        for (key, value) in labels {
-            context.vars.insert(key.to_string(), Term::Num(Answer::Single(value as f64)));
+        for (label, value) in labels {
+            context.vars.insert(label.to_string(), Term::Num(Answer::Single(value as f64)));
         }
-        context
-    };
-    let numerical = to_numerical_representation(expanded, evaluation_context)?;
+        EvaluationContext { context, movable, uses: Vec::new() }
+    }
 
-    Ok(numerical.into_boxed_slice())
+    /// Records how `expr`, added into an expression `sign` times, uses the movable labels.
+    /// Only sums and differences of labels, numbers and parenthesised expressions are understood.
+    fn record_uses(&mut self, expr: &str, sign: isize) {
+        let expr = expr.trim();
+        if let Some(inner) = expr.strip_prefix('(').and_then(|inner| inner.strip_suffix(')')) {
+            if closing_parenthesis(expr) == Some(expr.len() - 1) {
+                return self.record_uses(inner, sign);
+            }
+        }
+
+        let mut depth = 0;
+        let mut term_start = 0;
+        let mut term_sign = sign;
+        let mut terms = Vec::new();
+        for (i, c) in expr.char_indices() {
+            match c {
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                '+' | '-' if depth == 0 => {
+                    let term = expr[term_start..i].trim();
+                    // A sign in front of a term, rather than between two
+                    if term.is_empty() {
+                        term_sign = if c == '-' { -term_sign } else { term_sign };
+                        term_start = i + 1;
+                    } else if term.ends_with(|c: char| c.is_alphanumeric() || c == '_' || c == ')' || c == '.') {
+                        terms.push((term, term_sign));
+                        term_sign = if c == '-' { -sign } else { sign };
+                        term_start = i + 1;
+                    }
+                }
+                _ => {}
+            }
+        }
+        let term = expr[term_start..].trim();
+
+        if terms.is_empty() && term_sign == sign {
+            // A single term, such as `label` or `label * 2`
+            for name in term.split(|c: char| !(c.is_alphanumeric() || c == '_')) {
+                if let Some(&symbol) = self.movable.get(name) {
+                    self.record(symbol, if name == term { Some(sign) } else { None });
+                }
+            }
+            return;
+        }
+        terms.push((term, term_sign));
+        for (term, term_sign) in terms {
+            self.record_uses(term, term_sign);
+        }
+    }
+
+    fn record(&mut self, symbol: Option<&'a str>, times: Option<isize>) {
+        match self.uses.iter_mut().find(|(used, _)| *used == symbol) {
+            Some((_, total)) => *total = total.and_then(|total| times.map(|times| total + times)),
+            None => self.uses.push((symbol, times)),
+        }
+    }
 }
 
-/// Returns a vec of lines with comments, trailing whitespace, and leading whitespace removed.
+/// The byte index of the `)` closing the `(` `expr` starts with.
+fn closing_parenthesis(expr: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in expr.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 1 => return Some(i),
+            ')' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Returns a vec of lines with comments, trailing whitespace, and leading whitespace removed,
+/// each with its line number in the input.
 /// Takes everything until EOF or EINDPR
-fn as_filtered_lines(input: &str) -> Vec<&str> {
+fn as_filtered_lines(input: &str) -> Vec<(usize, &str)> {
     let mut lines = Vec::new();
-    for line in input.lines() {
+    for (index, line) in input.lines().enumerate() {
         // remove comments
-        let without_comment = line.splitn(2, '|').next().unwrap();
+        let without_comment = &line[..find_outside_strings(line, '|').unwrap_or(line.len())];
         // trim whitespace
         let x = without_comment.trim();
 
@@ -79,21 +341,23 @@ fn as_filtered_lines(input: &str) -> Vec<&str> {
         }
 
         if !x.is_empty() {
-            lines.push(x);
+            lines.push((index + 1, x)); // line numbers start at 1
         }
     }
 
     lines
 }
 
-/// Parses filtered code, expanding RESGR where needed, and removing labels.
-fn expand_and_omit_labels<'a>(input: &Vec<&'a str>) -> Result<(Vec<Line<'a>>, HashMap<&'a str, usize>), CompilationError<'a>> {
+/// Parses filtered code, expanding RESGR where needed, removing labels and collecting `EXPORT` and `IMPORT`.
+fn expand_and_omit_labels<'a>(input: &[(usize, &'a str)]) -> Result<Layout<'a>, CompilationError<'a>> {
     let mut address_counter = 0usize;
     let mut lines = Vec::new();
     let mut labels = HashMap::new();
-    let empty_context = Context::new();
-    for line_number in 0..input.len() {
-        let line = input[line_number];
+    let mut reserved = Vec::new();
+    let mut exports = Vec::new();
+    let mut imports = Vec::new();
+    let mut empty_context = EvaluationContext::new(Vec::new(), HashMap::new());
+    for &(line_number, line) in input {
 
         let (label, line_without_label) = omit_label(line);
         let line_without_label = line_without_label.trim();
@@ -106,21 +370,39 @@ fn expand_and_omit_labels<'a>(input: &Vec<&'a str>) -> Result<(Vec<Line<'a>>, Ha
 
         let line_struct = Line {
             address: address_counter,
-            line_number: line_number + 1, // line numbers start at 1
+            line_number,
             line: line_without_label,
         };
 
-        let (insn, operand) = trimmed_split(line_without_label, ' ');
-        if insn == "RESGR" {
+        let (insn, operand) = trimmed_split(line_without_label, " ");
+        if line_without_label.starts_with('"') {
+            let string = parse_string_literal(line_without_label)
+                .map_err(|reason| CompilationError::MalformedString(line_struct, reason))?;
+            lines.push(line_struct);
+            address_counter += string.chars().count() + 1; // +1 for the terminator
+        } else if insn == "RESGR" {
             if let Some(operand) = operand {
-                let value = calculate_expression(operand, &empty_context)
+                let value = calculate_expression(operand, &mut empty_context)
                     .map_err(|e| CompilationError::MathEval(line_struct.clone(), e))?;
                 let value: usize = usize::try_from(value)
                     .map_err(|_| CompilationError::NegativeRegisters { line: line_struct, expr: operand, value })?;
+                if value > 0 {
+                    reserved.push((address_counter, value));
+                }
                 address_counter += value;
             } else {
                 return Err(CompilationError::NoOperand { line: line_struct, opcode: "RESGR" });
             }
+        } else if insn == "EXPORT" || insn == "IMPORT" {
+            // EXPORT label, label, ... and IMPORT label, label, ...
+            let operand = operand.ok_or(CompilationError::NoOperand { line: line_struct, opcode: insn })?;
+            for name in operand.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+                if insn == "EXPORT" {
+                    exports.push((line_struct, name));
+                } else {
+                    imports.push(name);
+                }
+            }
         } else {
             lines.push(line_struct);
             address_counter += 1;
@@ -128,31 +410,62 @@ fn expand_and_omit_labels<'a>(input: &Vec<&'a str>) -> Result<(Vec<Line<'a>>, Ha
 
     }
 
-    Ok((lines, labels))
+    Ok(Layout { lines, labels, reserved, exports, imports })
 }
 
-fn to_numerical_representation(lines: Vec<Line>, evaluation_context: Context<f64>) -> Result<Vec<(usize, isize)>, CompilationError> {
+/// Turns every line into words. The labels of `evaluation_context` that are movable get relocations.
+fn to_numerical_representation<'a>(lines: Vec<Line<'a>>, evaluation_context: &mut EvaluationContext) -> Result<Assembled, CompilationError<'a>> {
     let mut out = Vec::new();
+    let mut source_map = Vec::new();
+    let mut relocations = Vec::new();
+    let mut relocatable = true;
     for line in lines {
         let str = line.line;
 
         let (_, line_without_label) = omit_label(str);
         let line_without_label = line_without_label.trim();
-        let numerical = match insn_to_numerical(line_without_label, &line, &evaluation_context) {
-            Ok(insn) => insn,
-            Err(CompilationError::NoCompilation) => calculate_expression(line_without_label, &evaluation_context)
-                .map_err(|e| CompilationError::Incomprehensible(line.clone(), e))?,
-            e => e?
+        if line_without_label.starts_with('"') {
+            let string = parse_string_literal(line_without_label)
+                .map_err(|reason| CompilationError::MalformedString(line, reason))?;
+            for (offset, value) in word::encode_string(&string).into_iter().enumerate() {
+                out.push((line.address + offset, value));
+                source_map.push((line.address + offset, line.line_number));
+            }
+            continue;
+        }
+        evaluation_context.uses.clear();
+        let (numerical, kind) = match insn_to_numerical(line_without_label, &line, evaluation_context) {
+            Ok(insn) => (insn, RelocationKind::Operand),
+            Err(CompilationError::NoCompilation) => {
+                evaluation_context.uses.clear();
+                let value = calculate_expression(line_without_label, evaluation_context)
+                    .map_err(|e| CompilationError::Incomprehensible(line.clone(), e))?;
+                (value, RelocationKind::Word)
+            }
+            Err(e) => return Err(e),
         };
+        for &(symbol, times) in evaluation_context.uses.iter() {
+            match times {
+                Some(0) => {}
+                Some(1) => relocations.push(Relocation { address: line.address, kind, symbol: symbol.map(str::to_string) }),
+                _ if symbol.is_none() => relocatable = false,
+                _ => return Err(CompilationError::Unrelocatable(line)),
+            }
+        }
 
-        out.push((line.address, numerical))
+        out.push((line.address, numerical));
+        source_map.push((line.address, line.line_number));
     }
 
-    Ok(out)
+    Ok(Assembled { words: out, lines: source_map, relocations, relocatable })
 }
 
-fn insn_to_numerical<'a>(insn: &'a str, line: &Line<'a>, evaluation_context: &Context<f64>) -> Result<isize, CompilationError<'a>> {
-    let (original_opcode, rhs) = trimmed_split(insn, |c: char| c.is_whitespace());
+fn insn_to_numerical<'a>(insn: &'a str, line: &Line<'a>, evaluation_context: &mut EvaluationContext) -> Result<isize, CompilationError<'a>> {
+    let insn = insn.trim();
+    let (original_opcode, rhs) = match insn.find(char::is_whitespace) {
+        Some(i) => (&insn[..i], Some(insn[i..].trim())),
+        None => (insn, None),
+    };
     let opcode = original_opcode.to_uppercase();
     let opcode = opcode.as_str();
 
@@ -160,7 +473,7 @@ fn insn_to_numerical<'a>(insn: &'a str, line: &Line<'a>, evaluation_context: &Co
         return Ok(insn);
     }
 
-    let (opcode, int) = trimmed_split(opcode, '.');
+    let (opcode, int) = trimmed_split(opcode, ".");
 
     let int: Option<char> = match int {
         None => None,
@@ -172,7 +485,7 @@ fn insn_to_numerical<'a>(insn: &'a str, line: &Line<'a>, evaluation_context: &Co
         }
     };
 
-    const LEFTOVER_INSNS: [&str; 12] = ["HIA", "BIG", "OPT", "AFT", "VER", "DEL", "MOD", "VGL", "VSP", "SBR", "BST", "HST"];
+    const LEFTOVER_INSNS: [&str; 13] = ["HIA", "BIG", "OPT", "AFT", "VER", "DEL", "MOD", "VGL", "SPR", "VSP", "SBR", "BST", "HST"];
     if !LEFTOVER_INSNS.contains(&opcode) {
         return Err(CompilationError::NoCompilation);
     }
@@ -210,28 +523,16 @@ fn insn_to_numerical<'a>(insn: &'a str, line: &Line<'a>, evaluation_context: &Co
 }
 
 fn parse_no_operand(opcode: &str) -> Option<isize> {
-    match opcode {
-        "KTG" => return Some(insn(FC_KTG, NA, NA, NA, NA, NA)),
-        "LEZ" => return Some(insn(FC_LEZ, NA, NA, NA, NA, NA)),
-        "DRU" => return Some(insn(FC_DRU, NA, NA, NA, NA, NA)),
-        "NWL" => return Some(insn(FC_NWL, NA, NA, NA, NA, NA)),
-        "DRS" => return Some(insn(FC_DRS, NA, NA, NA, NA, NA)),
-        "STP" => return Some(insn(FC_STP, NA, NA, NA, NA, NA)),
-        "NOP" => return Some(0), // TODO: HIA R0, R0
-        _ => None
+    if opcode == "NOP" {
+        return Some(0); // TODO: HIA R0, R0
     }
+    FunctionCode::from_mnemonic(opcode)
+        .filter(|fc| !fc.takes_operand())
+        .map(encode_without_operand)
 }
 
-fn operand_to_reg(op: &str) -> Option<usize> {
-    if op.len() != 2 || &op[0..1] != "R" {
-        return None;
-    }
-
-    let r = &op[1..2];
-    let r = r.parse().ok()?;
-    // It is not necessary to check that 0 <= R <= 9,
-    // as all of those values exclusively fit the condition that op.len() == 2.
-    Some(r)
+fn operand_to_reg(op: &str) -> Option<Register> {
+    Register::from_name(op)
 }
 
 macro_rules! deny_any_interpretation {
@@ -260,44 +561,44 @@ macro_rules! allow_only_interpretations {
 }
 
 #[inline]
-fn parse_single_operand<'a>(opcode: &str, int: &Option<char>, rhs: &'a str, line: Line<'a>, evaluation_context: &Context<f64>) -> Result<isize, CompilationError<'a>> {
+fn parse_single_operand<'a>(opcode: &str, int: &Option<char>, rhs: &'a str, line: Line<'a>, evaluation_context: &mut EvaluationContext) -> Result<isize, CompilationError<'a>> {
     // Single-operand instructions:
     match opcode {
         "HST" => {
             deny_any_interpretation!(int, opcode.to_string(), line);
             // HST becomes HIA <reg>, 0(R8+)
-            let r = operand_to_reg(rhs).into_result().map_err(|_| CompilationError::NotARegister {
+            let r = operand_to_reg(rhs).ok_or_else(|| CompilationError::NotARegister {
                 line,
                 malformed_operand: rhs.to_string(),
             })?;
-            Ok(self::insn(FC_HIA, MOD1_VALUE, MOD2_INDEXATION_POST_INC, r as isize, 8, 0))
+            Ok(encode(FunctionCode::HIA, Mode1::Direct, Mode2::PostIncrement, r, Register::R8, 0))
         }
         "BST" => {
             deny_any_interpretation!(int, opcode.to_string(), line);
             // BST becomes BIG <reg>, 0(-R8)
-            let r = operand_to_reg(rhs).into_result().map_err(|_| CompilationError::NotARegister {
+            let r = operand_to_reg(rhs).ok_or_else(|| CompilationError::NotARegister {
                 line,
                 malformed_operand: rhs.to_string(),
             })?;
-            Ok(self::insn(FC_BIG, MOD1_VALUE, MOD2_INDEXATION_PRE_DEC, r as isize, 8, 0))
+            Ok(encode(FunctionCode::BIG, Mode1::Address, Mode2::PreDecrement, r, Register::R8, 0))
         }
         "SBR" | "SPR" => {
             let int = allow_only_interpretations!(int, opcode.to_string(), line, 'd', 'i');
             let address = calculate_expression(rhs, evaluation_context)
                 .map_err(|e| CompilationError::MathEval(line, e))?;
-            let fc = if opcode == "SBR" { FC_SBR } else { FC_SPR };
-            Ok(match int {
-                'd' => self::insn(fc, MOD1_ADDRESS, NA, NA, NA, address),
-                'i' => self::insn(fc, MOD1_INDIRECT_ADDRESS, NA, NA, NA, address),
-                _ => panic!("Invalid interpretation that should have been filtered")
-            })
+            let fc = if opcode == "SBR" { FunctionCode::SBR } else { FunctionCode::SPR };
+            // Jumps address their operand, so `.d` jumps to the operand itself.
+            let mode1 = Mode1::from_interpretation(int)
+                .and_then(Mode1::less_indirect)
+                .expect("Invalid interpretation that should have been filtered");
+            Ok(encode(fc, mode1, Mode2::NoIndex, Register::R0, Register::R0, address))
         }
         _ => Err(CompilationError::NoCompilation)
     }
 }
 
 #[inline]
-fn parse_double_operand<'a>(opcode: &str, int: &Option<char>, left_op: &'a str, right_op: &'a str, line: Line<'a>, evaluation_context: &Context<f64>) -> Result<isize, CompilationError<'a>> {
+fn parse_double_operand<'a>(opcode: &str, int: &Option<char>, left_op: &'a str, right_op: &'a str, line: Line<'a>, evaluation_context: &mut EvaluationContext) -> Result<isize, CompilationError<'a>> {
     // Preprocess reg-reg instructions
     let (_int, left_op, right_op) = if let (Some(left_reg), Some(right_reg)) = (operand_to_reg(left_op), operand_to_reg(right_op)) {
         match opcode {
@@ -305,7 +606,7 @@ fn parse_double_operand<'a>(opcode: &str, int: &Option<char>, left_op: &'a str,
                 if let Some(_) = int {
                     return Err(CompilationError::RegRegInterpretation(line, opcode.to_string()));
                 }
-                (&Some('w'), format!("R{}", left_reg), format!("0(R{})", right_reg))
+                (&Some('w'), left_reg.to_string(), format!("0({})", right_reg))
             }
             _ => return Err(CompilationError::RegRegUnsupported(line.clone(), opcode.to_string()))
         }
@@ -313,46 +614,40 @@ fn parse_double_operand<'a>(opcode: &str, int: &Option<char>, left_op: &'a str,
         (int, left_op.to_string(), right_op.to_string())
     };
 
-    let fc = match opcode {
-        "HIA" => FC_HIA,
-        "BIG" => FC_BIG,
-        "OPT" => FC_OPT,
-        "AFT" => FC_AFT,
-        "VER" => FC_VER,
-        "DEL" => FC_DEL,
-        "MOD" => FC_MOD,
-        "VGL" => FC_VGL,
-        "VSP" => FC_VSP,
-        _ => panic!("Found opcode that should have been filtered")
-    };
+    let fc = FunctionCode::from_mnemonic(opcode).expect("Found opcode that should have been filtered");
 
     let (op, mod2, idx) = parse_address_indexed(right_op, line.clone(), evaluation_context)?;
     let int = match int {
         None => 'd',
         Some(i) => *i,
     };
-    let mod1: isize = {
-        let mut mod1 = match int {
-            'w' => 1,
-            'd' => 3,
-            'i' => 4,
-            _ => panic!("Invalid interpretation")
-        };
-        if let "BIG" | "SPR" | "VSP" = opcode {
-            mod1 -= 1;
+    let mod1 = {
+        let mod1 = Mode1::from_interpretation(int).expect("Invalid interpretation");
+        if fc.addresses_operand() {
+            // There is nothing less indirect than a value, so `BIG.w` and the like can't be encoded.
+            mod1.less_indirect()
+                .ok_or_else(|| CompilationError::UnsupportedInterpretation(line.clone(), opcode.to_string(), vec!('d', 'i')))?
+        } else {
+            mod1
         }
-        mod1
     };
 
-    let reg = operand_to_reg(left_op.as_str()).into_result().map_err(|_| CompilationError::NotARegister { line, malformed_operand: left_op })? as isize;
+    let reg = if fc == FunctionCode::VSP {
+        // The accumulator field of VSP holds its condition
+        Condition::from_mnemonic(left_op.as_str())
+            .map(Condition::register)
+            .ok_or_else(|| CompilationError::NotACondition { line, malformed_operand: left_op.clone() })?
+    } else {
+        operand_to_reg(left_op.as_str()).ok_or_else(|| CompilationError::NotARegister { line, malformed_operand: left_op.clone() })?
+    };
 
-    Ok(self::insn(fc, mod1, mod2, reg, idx, op))
+    Ok(encode(fc, mod1, mod2, reg, idx, op))
 }
 
 /// Parse an operand in the form of ADDRESS\[(\[+-\]Rx\[+-\])\]
 ///
 /// Returns a tuple `(operand, mod2, idx)`
-fn parse_address_indexed<'a>(operand: String, line: Line<'a>, evaluation_context: &Context<f64>) -> Result<(isize, isize, isize), CompilationError<'a>> {
+fn parse_address_indexed<'a>(operand: String, line: Line<'a>, evaluation_context: &mut EvaluationContext) -> Result<(isize, Mode2, Register), CompilationError<'a>> {
     let (address, indexation) = trimmed_split(operand.as_str(), "(");
 
     let address = calculate_expression(address, evaluation_context)
@@ -360,67 +655,225 @@ fn parse_address_indexed<'a>(operand: String, line: Line<'a>, evaluation_context
 
     let (mod2, idx) = if let Some(indexation) = indexation {
         let indexation = indexation.trim_end_matches(")");
-        let first_char = &indexation[0..=1];
+        let first_char = &indexation[0..1];
         let last_char = &indexation[indexation.len() - 1..];
         let (mod2, reg) = if let "+" | "-" = first_char {
             let rest = &indexation[1..];
-            (if first_char == "+" { MOD2_INDEXATION_PRE_INC } else { MOD2_INDEXATION_PRE_DEC }, rest)
+            (if first_char == "+" { Mode2::PreIncrement } else { Mode2::PreDecrement }, rest)
         } else if let "+" | "-" = last_char {
             let rest = &indexation[0..indexation.len() - 1];
-            (if last_char == "+" { MOD2_INDEXATION_POST_INC } else { MOD2_INDEXATION_POST_DEC }, rest)
+            (if last_char == "+" { Mode2::PostIncrement } else { Mode2::PostDecrement }, rest)
         } else {
-            (MOD2_INDEXATION, indexation)
+            (Mode2::Index, indexation)
         };
         let reg = operand_to_reg(reg)
-            .into_result()
-            .map_err(|_| CompilationError::NotARegister {
+            .ok_or_else(|| CompilationError::NotARegister {
                 line,
                 malformed_operand: reg.to_string(),
             })?;
 
-        (mod2, reg as isize)
+        (mod2, reg)
     } else {
-        (MOD2_NO_INDEXATION, 9)
+        (Mode2::NoIndex, Register::R0)
     };
 
     Ok((address, mod2, idx))
 }
 
 /// Removes the label from a string without any other operations such as trimming. Label may be `None` if there is none present.
+/// Colons inside a string literal don't count:
+/// ```text
+/// "look: I can't confuse the compiler!"   | this is not a label
+/// ```
 fn omit_label(line: &str) -> (Option<&str>, &str) {
-    // TODO: omit string literals from labels:
-    /*
-    HIA.a R0, 4
-    DRS
-    STP
-    "look: I can confuse the compiler!"   | this is not a label
-    */
-
-    let mut split = line.rsplitn(2, ':');
-    let rhs = split.next().unwrap();
-    let lhs = split.next();
-    (lhs, rhs)
-}
-
-fn trimmed_split<'a, P: Pattern<'a>>(string: &'a str, pattern: P) -> (&'a str, Option<&'a str>) {
-    let mut splitn = string.trim().splitn(2, pattern);
+    match rfind_outside_strings(line, ':') {
+        Some(i) => (Some(&line[..i]), &line[i + 1..]),
+        None => (None, line),
+    }
+}
+
+/// The byte indices of all occurrences of `needle` that are not inside a string literal.
+fn indices_outside_strings(line: &str, needle: char) -> impl Iterator<Item = usize> + '_ {
+    let mut in_string = false;
+    let mut escaped = false;
+    line.char_indices().filter_map(move |(i, c)| {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            None
+        } else if c == '"' {
+            in_string = true;
+            None
+        } else if c == needle {
+            Some(i)
+        } else {
+            None
+        }
+    })
+}
+
+fn find_outside_strings(line: &str, needle: char) -> Option<usize> {
+    indices_outside_strings(line, needle).next()
+}
+
+fn rfind_outside_strings(line: &str, needle: char) -> Option<usize> {
+    indices_outside_strings(line, needle).last()
+}
+
+/// Parses a string literal such as `"Hello, world\n"`, which must make up the entire line.
+/// Supports the escapes `\n`, `\t`, `\"` and `\\`.
+fn parse_string_literal(literal: &str) -> Result<String, &'static str> {
+    let mut chars = literal.chars();
+    if chars.next() != Some('"') {
+        return Err("a string literal starts with `\"`");
+    }
+    let mut string = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                return if chars.as_str().trim().is_empty() {
+                    Ok(string)
+                } else {
+                    Err("nothing may follow the closing `\"`")
+                };
+            }
+            '\\' => string.push(match chars.next() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('"') => '"',
+                Some('\\') => '\\',
+                _ => return Err("unknown escape sequence, expected one of `\\n`, `\\t`, `\\\"` or `\\\\`"),
+            }),
+            c => string.push(c),
+        }
+    }
+    Err("missing closing `\"`")
+}
+
+fn trimmed_split<'a>(string: &'a str, separator: &str) -> (&'a str, Option<&'a str>) {
+    let mut splitn = string.trim().splitn(2, separator);
     (splitn.next().unwrap().trim(), splitn.next().map(|s| s.trim()))
 }
 
 /// Calculate an integer expression.
 /// If multiple answers are possible, arbitrarily return the first one found.
 /// Answers are calculated in f64 and converted to isize.
-fn calculate_expression(expr: &str, ctx: &Context<f64>) -> Result<isize, EvalError> {
-    match mexprp::eval_ctx::<f64>(expr, ctx) {
+/// How the expression uses the movable labels is added to the `uses` of the context.
+fn calculate_expression(expr: &str, ctx: &mut EvaluationContext) -> Result<isize, EvalError> {
+    if !ctx.movable.is_empty() {
+        ctx.record_uses(expr, 1);
+    }
+    let expr = substitute_numbered_labels(expr, &ctx.context);
+    match mexprp::eval_ctx::<f64>(&expr, &ctx.context) {
         Ok(Answer::Single(answer)) => Ok(answer as isize),
         Ok(Answer::Multiple(v)) => Ok(*v.first().unwrap() as isize),
         Err(e) => Err(e)
     }
 }
 
-#[inline]
-fn insn(op: isize, m1: isize, m2: isize, acc: isize, ind: isize, operand: isize) -> isize {
-    let mut o = operand % 10_000;
-    if o < 0 { o += 10_000 }
-    return op * 1_00_0_0_0000 + m1 * 10_0_0_0000 + m2 * 10_0_0000 + acc * 1_0_0000 + ind * 1_0000 + o;
-}
\ No newline at end of file
+/// mexprp only reads alphabetic variable names, so a label such as `loop2` or `L0007`
+/// is replaced by its value before the expression is handed to it.
+fn substitute_numbered_labels(expr: &str, ctx: &Context<f64>) -> String {
+    let mut out = String::with_capacity(expr.len());
+    let mut rest = expr;
+    while let Some(c) = rest.chars().next() {
+        // A name starts with a letter or underscore that doesn't continue a number or another name
+        let continues_token = matches!(out.chars().last(), Some(p) if p.is_alphanumeric() || p == '_');
+        if (c.is_alphabetic() || c == '_') && !continues_token {
+            let end = rest.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(rest.len());
+            let name = &rest[..end];
+            match ctx.vars.get(name) {
+                Some(Term::Num(Answer::Single(value))) if name.chars().any(|c| c.is_ascii_digit()) => {
+                    out.push_str(&format!("({})", value));
+                }
+                _ => out.push_str(name),
+            }
+            rest = &rest[end..];
+        } else {
+            out.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INDEXATIONS: [&str; 6] = ["", "(R2)", "(+R2)", "(R2+)", "(-R2)", "(R2-)"];
+
+    /// Every instruction in every interpretation and indexation `compile` accepts for it, one per line.
+    fn every_instruction() -> Vec<String> {
+        let mut lines = Vec::new();
+        for fc in FunctionCode::ALL.iter() {
+            let mnemonic = fc.mnemonic();
+            match fc {
+                _ if !fc.takes_operand() => lines.push(mnemonic.to_string()),
+                FunctionCode::SPR | FunctionCode::SBR => {
+                    for interpretation in ["", ".d", ".i"].iter() {
+                        lines.push(format!("{}{} 12", mnemonic, interpretation));
+                    }
+                }
+                _ => {
+                    let interpretations: &[&str] = if fc.addresses_operand() { &["", ".d", ".a", ".i"] } else { &["", ".w", ".d", ".a", ".i"] };
+                    let first = if *fc == FunctionCode::VSP { "NNEG" } else { "R1" };
+                    for interpretation in interpretations {
+                        for indexation in INDEXATIONS.iter() {
+                            lines.push(format!("{}{} {}, -3{}", mnemonic, interpretation, first, indexation));
+                        }
+                    }
+                }
+            }
+        }
+        lines
+    }
+
+    #[test]
+    fn assembly_round_trips_through_the_disassembler() {
+        let lines = every_instruction();
+        for line in lines.iter() {
+            assert!(compile(line).is_ok(), "`{}` should compile", line);
+        }
+        let image = compile(&lines.join("\n")).unwrap();
+        let disassembly = disassembler::disassemble(&image);
+        assert_eq!(compile(&disassembly).unwrap(), image, "{}", disassembly);
+    }
+
+    #[test]
+    fn every_encoding_round_trips_through_the_disassembler() {
+        let mut image = Vec::new();
+        for fc in FunctionCode::ALL.iter() {
+            for mode1 in (1..=4).filter_map(Mode1::from_digit) {
+                for mode2 in (1..=6).filter_map(Mode2::from_digit) {
+                    for &(acc, index) in [(Register::R0, Register::R0), (Register::R1, Register::R2)].iter() {
+                        for &operand in [-5000, -1, 0, 7, 4999].iter() {
+                            image.push((image.len(), encode(*fc, mode1, mode2, acc, index, operand)));
+                        }
+                    }
+                }
+            }
+        }
+        let disassembly = disassembler::disassemble(&image);
+        assert_eq!(compile(&disassembly).unwrap().to_vec(), image);
+    }
+
+    #[test]
+    fn disassembles_jumps_with_labels() {
+        let image = compile("start: HIA.w R1, 1\nSPR start\nVSP POS, end\nRESGR 2\nend: STP").unwrap();
+        assert_eq!(disassembler::disassemble(&image), "L0000: HIA.w R1, 1\nSPR L0000\nVSP POS, L0005\nRESGR 2\nL0005: STP\n");
+    }
+
+    #[test]
+    fn string_literals_keep_colons_bars_and_escapes() {
+        let source = "HIA.a R0, msg | point DRS at the string\nDRS\nSTP\nmsg: \"time: 12 | \\\"ok\\\"\\n\" | the greeting";
+        let image = compile(source).unwrap();
+        let string: Vec<isize> = image.iter().filter(|&&(address, _)| address >= 3).map(|&(_, value)| value).collect();
+        assert_eq!(string, word::encode_string("time: 12 | \"ok\"\n"));
+        assert_eq!(image[0].1, encode(FunctionCode::HIA, Mode1::Address, Mode2::NoIndex, Register::R0, Register::R0, 3));
+    }
+}
@@ -0,0 +1,53 @@
+use crate::Register;
+
+/// The condition of a `VSP` instruction, stored in its accumulator digit.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Condition {
+    NUL = 1,
+    NNEG = 2,
+    NPOS = 3,
+    POS = 6,
+    NEG = 7,
+    NNUL = 8,
+}
+
+impl Condition {
+    pub const ALL: [Condition; 6] = [
+        Condition::NUL,
+        Condition::NNEG,
+        Condition::NPOS,
+        Condition::POS,
+        Condition::NEG,
+        Condition::NNUL,
+    ];
+
+    pub fn from_digit(digit: usize) -> Option<Self> {
+        Condition::ALL.iter().copied().find(|c| c.digit() == digit)
+    }
+
+    /// Looks up a condition by its mnemonic. The lookup is case-insensitive.
+    pub fn from_mnemonic(mnemonic: &str) -> Option<Self> {
+        Condition::ALL.iter().copied().find(|c| c.mnemonic().eq_ignore_ascii_case(mnemonic))
+    }
+
+    pub fn digit(self) -> usize {
+        self as usize
+    }
+
+    /// The accumulator field that encodes this condition in a `VSP` word.
+    pub fn register(self) -> Register {
+        Register::new(self.digit()).unwrap()
+    }
+
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            Condition::NUL => "NUL",
+            Condition::NNEG => "NNEG",
+            Condition::NPOS => "NPOS",
+            Condition::POS => "POS",
+            Condition::NEG => "NEG",
+            Condition::NNUL => "NNUL",
+        }
+    }
+}
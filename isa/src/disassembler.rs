@@ -0,0 +1,137 @@
+//! Turning instruction words back into the assembly dasm reads.
+
+use std::collections::BTreeSet;
+
+use crate::word::{self, OPERAND_MODULUS};
+use crate::{Condition, DecodedInstruction, FunctionCode, Mode1, Mode2, Register};
+
+/// Turns a memory image, as produced by dasm's `compile`, back into DRAMA assembly.
+///
+/// Static jump targets get a synthesized label (`L0007`), unused cells become `RESGR`,
+/// and words that are not an instruction `compile` could have produced are written as data.
+/// Compiling the output gives back the same image.
+pub fn disassemble(image: &[(usize, isize)]) -> String {
+    let mut image = image.to_vec();
+    image.sort_by_key(|&(address, _)| address);
+
+    let targets: BTreeSet<usize> = image.iter()
+        .filter_map(|&(_, value)| DecodedInstruction::decode(value).ok())
+        .filter_map(|insn| jump_target(&insn))
+        .collect();
+
+    let mut out = String::new();
+    let mut address_counter = 0usize;
+    for &(address, value) in image.iter() {
+        reserve_until(&mut out, &mut address_counter, address, &targets);
+
+        if targets.contains(&address) {
+            out.push_str(&label(address));
+            out.push_str(": ");
+        }
+        let text = DecodedInstruction::decode(value).ok()
+            .and_then(|insn| render(&insn, label))
+            .unwrap_or_else(|| value.to_string());
+        out.push_str(&text);
+        out.push('\n');
+        address_counter = address + 1;
+    }
+    // Jump targets past the end of the image still need a definition
+    if let Some(&last) = targets.range(address_counter..).last() {
+        reserve_until(&mut out, &mut address_counter, last, &targets);
+        out.push_str(&label(last));
+        out.push_str(":\n");
+    }
+
+    out
+}
+
+/// Emits `RESGR` lines up to `address`, defining any labels that fall in between.
+fn reserve_until(out: &mut String, address_counter: &mut usize, address: usize, targets: &BTreeSet<usize>) {
+    for &target in targets.range(*address_counter..address) {
+        if target > *address_counter {
+            out.push_str(&format!("RESGR {}\n", target - *address_counter));
+        }
+        out.push_str(&label(target));
+        out.push_str(":\n");
+        *address_counter = target;
+    }
+    if address > *address_counter {
+        out.push_str(&format!("RESGR {}\n", address - *address_counter));
+        *address_counter = address;
+    }
+}
+
+fn label(address: usize) -> String {
+    format!("L{:04}", address)
+}
+
+/// The address a jump goes to, if it is known without running the program.
+fn jump_target(insn: &DecodedInstruction) -> Option<usize> {
+    match insn.fc {
+        FunctionCode::SPR | FunctionCode::VSP | FunctionCode::SBR
+        if insn.mode1 == Mode1::Address && insn.mode2 == Mode2::NoIndex => {
+            Some(insn.operand.rem_euclid(OPERAND_MODULUS) as usize)
+        }
+        _ => None
+    }
+}
+
+/// Renders a single instruction as dasm would write it, with jump targets as plain addresses: `SPR 7`.
+/// Encodings `compile` never produces are shown by their mnemonic alone.
+pub fn disassemble_instruction(insn: &DecodedInstruction) -> String {
+    render(insn, |target| target.to_string()).unwrap_or_else(|| insn.fc.mnemonic().to_string())
+}
+
+/// Renders an instruction in the syntax `compile` accepts, naming static jump targets with `target_name`.
+/// Returns `None` for encodings `compile` never produces, as those can only be reproduced as data.
+fn render(insn: &DecodedInstruction, target_name: fn(usize) -> String) -> Option<String> {
+    let mnemonic = insn.fc.mnemonic();
+    if !insn.fc.takes_operand() {
+        return if insn.encode() == word::encode_without_operand(insn.fc) {
+            Some(mnemonic.to_string())
+        } else {
+            None
+        };
+    }
+
+    let operand = match jump_target(insn) {
+        Some(target) => target_name(target),
+        None => insn.operand.to_string(),
+    };
+    let address = match insn.mode2 {
+        // `compile` fills in R0 for the unused index register
+        Mode2::NoIndex if insn.index != Register::R0 => return None,
+        Mode2::NoIndex => operand,
+        Mode2::Index => format!("{}({})", operand, insn.index),
+        Mode2::PreIncrement => format!("{}(+{})", operand, insn.index),
+        Mode2::PostIncrement => format!("{}({}+)", operand, insn.index),
+        Mode2::PreDecrement => format!("{}(-{})", operand, insn.index),
+        Mode2::PostDecrement => format!("{}({}-)", operand, insn.index),
+    };
+
+    let interpretation = if insn.fc.addresses_operand() {
+        insn.mode1.more_indirect()?
+    } else {
+        insn.mode1
+    };
+    // `.d` is the default interpretation
+    let suffix = match interpretation {
+        Mode1::Direct => String::new(),
+        m => format!(".{}", m.interpretation()),
+    };
+
+    match insn.fc {
+        FunctionCode::SPR | FunctionCode::SBR => {
+            // `compile` only accepts `.d` and `.i` for jumps
+            if insn.acc != Register::R0 || insn.mode2 != Mode2::NoIndex || !matches!(insn.mode1, Mode1::Address | Mode1::Direct) {
+                return None;
+            }
+            Some(format!("{}{} {}", mnemonic, suffix, address))
+        }
+        FunctionCode::VSP => {
+            let condition = Condition::from_digit(insn.acc.index())?;
+            Some(format!("{}{} {}, {}", mnemonic, suffix, condition.mnemonic(), address))
+        }
+        _ => Some(format!("{}{} {}, {}", mnemonic, suffix, insn.acc, address))
+    }
+}
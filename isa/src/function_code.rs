@@ -0,0 +1,140 @@
+/// The function code of an instruction, stored in the two leading digits of the word.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FunctionCode {
+    HIA = 11,
+    BIG = 12,
+    OPT = 21,
+    AFT = 22,
+    VER = 23,
+    DEL = 24,
+    MOD = 25,
+    VGL = 31,
+    SPR = 32,
+    VSP = 33,
+    SBR = 41,
+    KTG = 42,
+    /// Keer Terug uit Onderbreking: returns from an interrupt handler. Part of the interrupt extension.
+    KTO = 43,
+    /// OnderBrekingen Aan: enables interrupts. Part of the interrupt extension.
+    OBA = 44,
+    /// OnderBrekingen Uit: disables interrupts. Part of the interrupt extension.
+    OBU = 45,
+    LEZ = 71,
+    DRU = 72,
+    NWL = 73,
+    DRS = 74,
+    STP = 99,
+}
+
+impl FunctionCode {
+    /// Every function code of the instruction set, extensions included, in numerical order.
+    pub const ALL: [FunctionCode; 20] = [
+        FunctionCode::HIA,
+        FunctionCode::BIG,
+        FunctionCode::OPT,
+        FunctionCode::AFT,
+        FunctionCode::VER,
+        FunctionCode::DEL,
+        FunctionCode::MOD,
+        FunctionCode::VGL,
+        FunctionCode::SPR,
+        FunctionCode::VSP,
+        FunctionCode::SBR,
+        FunctionCode::KTG,
+        FunctionCode::KTO,
+        FunctionCode::OBA,
+        FunctionCode::OBU,
+        FunctionCode::LEZ,
+        FunctionCode::DRU,
+        FunctionCode::NWL,
+        FunctionCode::DRS,
+        FunctionCode::STP,
+    ];
+
+    pub fn from_code(code: usize) -> Option<Self> {
+        FunctionCode::ALL.iter().copied().find(|fc| fc.code() == code)
+    }
+
+    /// Looks up a function code by its mnemonic. The lookup is case-insensitive.
+    pub fn from_mnemonic(mnemonic: &str) -> Option<Self> {
+        FunctionCode::ALL.iter().copied().find(|fc| fc.mnemonic().eq_ignore_ascii_case(mnemonic))
+    }
+
+    pub fn code(self) -> usize {
+        self as usize
+    }
+
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            FunctionCode::HIA => "HIA",
+            FunctionCode::BIG => "BIG",
+            FunctionCode::OPT => "OPT",
+            FunctionCode::AFT => "AFT",
+            FunctionCode::VER => "VER",
+            FunctionCode::DEL => "DEL",
+            FunctionCode::MOD => "MOD",
+            FunctionCode::VGL => "VGL",
+            FunctionCode::SPR => "SPR",
+            FunctionCode::VSP => "VSP",
+            FunctionCode::SBR => "SBR",
+            FunctionCode::KTG => "KTG",
+            FunctionCode::KTO => "KTO",
+            FunctionCode::OBA => "OBA",
+            FunctionCode::OBU => "OBU",
+            FunctionCode::LEZ => "LEZ",
+            FunctionCode::DRU => "DRU",
+            FunctionCode::NWL => "NWL",
+            FunctionCode::DRS => "DRS",
+            FunctionCode::STP => "STP",
+        }
+    }
+
+    /// Whether the instruction uses its operand field at all.
+    /// Instructions without an operand ignore the modes, the registers and the operand of their word.
+    pub fn takes_operand(self) -> bool {
+        !matches!(self, FunctionCode::KTG | FunctionCode::KTO | FunctionCode::OBA | FunctionCode::OBU
+            | FunctionCode::LEZ | FunctionCode::DRU | FunctionCode::NWL | FunctionCode::DRS | FunctionCode::STP)
+    }
+
+    /// Whether the instruction uses its accumulator field.
+    /// For `VSP` the field holds a [`Condition`](crate::Condition) rather than a register.
+    pub fn takes_accumulator(self) -> bool {
+        self.takes_operand() && !matches!(self, FunctionCode::SPR | FunctionCode::SBR)
+    }
+
+    /// Whether the operand of this instruction names an address rather than a value.
+    ///
+    /// For these instructions every interpretation is one level less indirect than it is for the others:
+    /// `BIG.d` is encoded with [`Mode1::Address`](crate::Mode1::Address), `SPR.i` with [`Mode1::Direct`](crate::Mode1::Direct).
+    pub fn addresses_operand(self) -> bool {
+        matches!(self, FunctionCode::BIG | FunctionCode::SPR | FunctionCode::VSP | FunctionCode::SBR)
+    }
+
+    /// Whether the instruction belongs to the opt-in interrupt extension rather than to plain DRAMA.
+    pub fn is_interrupt_extension(self) -> bool {
+        matches!(self, FunctionCode::KTO | FunctionCode::OBA | FunctionCode::OBU)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes_and_mnemonics_round_trip() {
+        for &fc in FunctionCode::ALL.iter() {
+            assert_eq!(FunctionCode::from_code(fc.code()), Some(fc));
+            assert_eq!(FunctionCode::from_mnemonic(fc.mnemonic()), Some(fc));
+            assert_eq!(FunctionCode::from_mnemonic(&fc.mnemonic().to_lowercase()), Some(fc));
+        }
+        assert_eq!(FunctionCode::from_code(0), None);
+        assert_eq!(FunctionCode::from_code(13), None);
+        assert_eq!(FunctionCode::from_mnemonic("NOP"), None);
+    }
+
+    #[test]
+    fn all_is_in_numerical_order() {
+        assert!(FunctionCode::ALL.windows(2).all(|pair| pair[0].code() < pair[1].code()));
+    }
+}
@@ -0,0 +1,131 @@
+use std::fmt::{Display, Formatter};
+
+use crate::word::{self, OPERAND_MODULUS};
+use crate::{FunctionCode, Mode1, Mode2, Register};
+
+/// An instruction word, split into its typed fields.
+///
+/// Decoding and encoding are each other's inverse: `decode(word)?.encode()` gives back `word`
+/// (in its non-negative form), and every field survives `decode(instruction.encode())`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DecodedInstruction {
+    pub fc: FunctionCode,
+    pub mode1: Mode1,
+    pub mode2: Mode2,
+    /// The accumulator. For `VSP` this field holds the [`Condition`](crate::Condition) instead.
+    pub acc: Register,
+    pub index: Register,
+    /// The sign-extended operand, in `-5000..5000`.
+    pub operand: isize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    InvalidFunctionCode(usize),
+    InvalidMode1(usize),
+    InvalidMode2(usize),
+}
+
+impl DecodedInstruction {
+    pub fn decode(word: isize) -> Result<Self, DecodeError> {
+        let fields = word::split(word);
+        Ok(DecodedInstruction {
+            fc: FunctionCode::from_code(fields.fc).ok_or(DecodeError::InvalidFunctionCode(fields.fc))?,
+            mode1: Mode1::from_digit(fields.mode1).ok_or(DecodeError::InvalidMode1(fields.mode1))?,
+            mode2: Mode2::from_digit(fields.mode2).ok_or(DecodeError::InvalidMode2(fields.mode2))?,
+            acc: Register::new(fields.acc).unwrap(),
+            index: Register::new(fields.index).unwrap(),
+            operand: fields.operand,
+        })
+    }
+
+    pub fn encode(&self) -> isize {
+        word::encode(self.fc, self.mode1, self.mode2, self.acc, self.index, self.operand)
+    }
+}
+
+impl Display for DecodedInstruction {
+    /// Prints the word grouped as `fc_mo_a_i_operand`, e.g. `11_12_1_0_0005`.
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:02}_{}{}_{}_{}_{:04}",
+               self.fc.code(),
+               self.mode1.digit(),
+               self.mode2.digit(),
+               self.acc.index(),
+               self.index.index(),
+               self.operand.rem_euclid(OPERAND_MODULUS))
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::InvalidFunctionCode(fc) => write!(f, "`{:02}` is not a function code", fc),
+            DecodeError::InvalidMode1(m) => write!(f, "`{}` is not a valid first modus, expected 1 through 4", m),
+            DecodeError::InvalidMode2(m) => write!(f, "`{}` is not a valid second modus, expected 1 through 6", m),
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::inconsistent_digit_grouping)]
+mod tests {
+    use super::*;
+    use crate::word::{WORD_MAX, WORD_MIN};
+
+    const MODE1: [Mode1; 4] = [Mode1::Value, Mode1::Address, Mode1::Direct, Mode1::Indirect];
+    const MODE2: [Mode2; 6] = [Mode2::NoIndex, Mode2::Index, Mode2::PreIncrement, Mode2::PostIncrement, Mode2::PreDecrement, Mode2::PostDecrement];
+
+    #[test]
+    fn encode_and_decode_are_inverse() {
+        for &fc in FunctionCode::ALL.iter() {
+            for &mode1 in MODE1.iter() {
+                for &mode2 in MODE2.iter() {
+                    for &(acc, index) in [(0, 9), (9, 0), (3, 8)].iter() {
+                        for &operand in [-5000, -4999, -1, 0, 1, 4999].iter() {
+                            let insn = DecodedInstruction {
+                                fc,
+                                mode1,
+                                mode2,
+                                acc: Register::new(acc).unwrap(),
+                                index: Register::new(index).unwrap(),
+                                operand,
+                            };
+                            let word = insn.encode();
+                            assert_eq!(DecodedInstruction::decode(word), Ok(insn));
+                            assert_eq!(DecodedInstruction::decode(word).unwrap().encode(), word);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn decodes_negative_words_by_their_digits() {
+        // -8_888_999_999 is 11_11_1_0_0001 in ten's complement
+        let word = 11_11_1_0_0001 - crate::word::WORD_MODULUS;
+        let insn = DecodedInstruction::decode(word).unwrap();
+        assert_eq!((insn.fc, insn.mode1, insn.mode2, insn.acc, insn.operand), (FunctionCode::HIA, Mode1::Value, Mode2::NoIndex, Register::R1, 1));
+        assert_eq!(insn.encode(), 11_11_1_0_0001);
+    }
+
+    #[test]
+    fn rejects_invalid_fields() {
+        assert_eq!(DecodedInstruction::decode(0), Err(DecodeError::InvalidFunctionCode(0)));
+        assert_eq!(DecodedInstruction::decode(WORD_MIN), Err(DecodeError::InvalidFunctionCode(50)));
+        assert_eq!(DecodedInstruction::decode(WORD_MAX), Err(DecodeError::InvalidFunctionCode(49)));
+        assert_eq!(DecodedInstruction::decode(-1), Err(DecodeError::InvalidMode1(9)));
+        assert_eq!(DecodedInstruction::decode(11_51_0_0_0000), Err(DecodeError::InvalidMode1(5)));
+        assert_eq!(DecodedInstruction::decode(11_17_0_0_0000), Err(DecodeError::InvalidMode2(7)));
+    }
+
+    #[test]
+    fn displays_the_digit_groups() {
+        let insn = DecodedInstruction::decode(11_12_1_0_9999).unwrap();
+        assert_eq!(insn.operand, -1);
+        assert_eq!(insn.to_string(), "11_12_1_0_9999");
+    }
+}
@@ -0,0 +1,23 @@
+//! The DRAMA instruction set.
+//!
+//! This crate is the single definition of the opcode table, the addressing modes and the layout of an
+//! instruction word. Both the simulator and the dasm assembler depend on it, so that whatever dasm
+//! assembles is decoded the same way by the simulator.
+
+pub use crate::condition::Condition;
+pub use crate::function_code::FunctionCode;
+pub use crate::instruction::{DecodeError, DecodedInstruction};
+pub use crate::mode::{Mode1, Mode2};
+pub use crate::register::Register;
+
+mod condition;
+mod function_code;
+mod instruction;
+mod mode;
+mod register;
+pub mod disassembler;
+pub mod linker;
+pub mod listing;
+pub mod object;
+pub mod source_map;
+pub mod word;
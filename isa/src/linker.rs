@@ -0,0 +1,178 @@
+//! Combining the objects of several files into one that can be loaded.
+//!
+//! The objects are placed one after the other, in the order given, starting at address 0. Every import is
+//! resolved to the label of that name exported by one of the objects, and every relocation is applied.
+//! Execution starts at the entry point of the first object.
+//!
+//! Exported labels keep their name in the linked object. The other labels are only known within their own object,
+//! so several objects may use the same one: they are named after their object, as in `lib.dasm::loop`.
+
+use std::collections::HashMap;
+use std::fmt::Formatter;
+
+use crate::object::{AssembledWord, ObjectFile, Section};
+
+const MEMORY_SIZE: usize = 10_000;
+
+/// Links `objects`, each given with the name to mention it by in errors and [sections](ObjectFile::sections).
+pub fn link(objects: &[(String, ObjectFile)]) -> Result<ObjectFile, LinkError> {
+    let (_, first) = objects.first().ok_or(LinkError::NothingToLink)?;
+
+    let mut sections = Vec::new();
+    let mut start = 0;
+    for (name, object) in objects {
+        if !object.relocatable && start != 0 {
+            return Err(LinkError::NotRelocatable(name.clone()));
+        }
+        let size = object.size();
+        if start + size > MEMORY_SIZE {
+            return Err(LinkError::TooLarge { object: name.clone(), end: start + size });
+        }
+        sections.push(Section { name: name.clone(), start, size });
+        start += size;
+    }
+
+    // Every exported label, with its final address and the object exporting it
+    let mut exported: HashMap<&str, (usize, &str)> = HashMap::new();
+    for ((name, object), section) in objects.iter().zip(sections.iter()) {
+        for export in object.exports.iter() {
+            let address = object.address_of(export)
+                .ok_or_else(|| LinkError::UndefinedExport { symbol: export.clone(), object: name.clone() })?;
+            if let Some(&(_, first)) = exported.get(export.as_str()) {
+                return Err(LinkError::DuplicateSymbol { symbol: export.clone(), first: first.to_string(), second: name.clone() });
+            }
+            exported.insert(export, (section.start + address, name));
+        }
+    }
+
+    let mut linked = ObjectFile { entry_point: first.entry_point, ..ObjectFile::default() };
+    for ((name, object), section) in objects.iter().zip(sections.iter()) {
+        let resolve = |symbol: &str| exported.get(symbol)
+            .map(|&(address, _)| address)
+            .ok_or_else(|| LinkError::MissingSymbol { symbol: symbol.to_string(), object: name.clone() });
+        for import in object.imports.iter() {
+            resolve(import)?;
+        }
+
+        let mut words: Vec<AssembledWord> = object.words.iter()
+            .map(|word| AssembledWord { address: section.start + word.address, ..*word })
+            .collect();
+        for relocation in object.relocations.iter() {
+            let value = match &relocation.symbol {
+                Some(symbol) => resolve(symbol)?,
+                None => section.start,
+            };
+            // A relocation of an address without a word has nothing to adjust
+            if let Some(word) = words.iter_mut().find(|word| word.address == section.start + relocation.address) {
+                word.value = relocation.apply(word.value, value);
+            }
+        }
+        linked.words.extend(words);
+        linked.symbols.extend(object.symbols.iter().map(|(symbol, address)| {
+            let symbol = if object.exports.contains(symbol) { symbol.clone() } else { format!("{}::{}", name, symbol) };
+            (symbol, section.start + address)
+        }));
+        linked.reserved.extend(object.reserved.iter().map(|&(start, length)| (section.start + start, length)));
+    }
+    linked.symbols.sort_by(|(a, a_address), (b, b_address)| (a_address, a).cmp(&(b_address, b)));
+    linked.sections = sections;
+    Ok(linked)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LinkError {
+    NothingToLink,
+    /// Two objects export a label of the same name.
+    DuplicateSymbol { symbol: String, first: String, second: String },
+    /// An object imports a label no object exports.
+    MissingSymbol { symbol: String, object: String },
+    /// An object exports a label it doesn't define.
+    UndefinedExport { symbol: String, object: String },
+    /// The object doesn't fit in memory after the objects before it.
+    TooLarge { object: String, end: usize },
+    /// The object can only go at address 0, but isn't the first.
+    NotRelocatable(String),
+}
+
+impl std::error::Error for LinkError {}
+
+impl std::fmt::Display for LinkError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LinkError::NothingToLink => write!(f, "There is nothing to link"),
+            LinkError::DuplicateSymbol { symbol, first, second } => write!(f, "`{}` is exported by both {} and {}", symbol, first, second),
+            LinkError::MissingSymbol { symbol, object } => write!(f, "`{}`, imported by {}, is not exported by any file", symbol, object),
+            LinkError::UndefinedExport { symbol, object } => write!(f, "`{}` is exported by {}, but not defined there", symbol, object),
+            LinkError::TooLarge { object, end } => write!(f, "{} does not fit in memory: it would end at address {}", object, end),
+            LinkError::NotRelocatable(object) => write!(f, "{} uses its labels in a way that can't be adjusted, so it has to come first", object),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn object(text: &str) -> ObjectFile {
+        format!("DRAMA object v2\nrelocatable\n{}", text).parse().unwrap()
+    }
+
+    /// Jumps to `print` in the other object, then to its own `loop`.
+    fn program() -> ObjectFile {
+        object("symbol loop 0001\nimport print\nrelocate 0000 operand print\nrelocate 0001 operand\n\
+                0000: 3200000000\n0001: 3200000001\n0002: 9999000000")
+    }
+
+    /// Has a `loop` of its own, and a word holding the address of `print` plus 1.
+    fn library() -> ObjectFile {
+        object("symbol print 0000\nsymbol loop 0001\nexport print\nrelocate 0001 operand\nrelocate 0003 word\n\
+                0000: 7100000000\n0001: 3200000001\n0002: 9999000000\n0003: 1")
+    }
+
+    #[test]
+    fn places_and_relocates_objects() {
+        let linked = link(&[("main".to_string(), program()), ("lib".to_string(), library())]).unwrap();
+        assert_eq!(linked.image(), vec![
+            (0, 3200000003),
+            (1, 3200000001),
+            (2, 9999000000),
+            (3, 7100000000),
+            (4, 3200000004),
+            (5, 9999000000),
+            (6, 4),
+        ]);
+        assert_eq!(linked.sections, vec![
+            Section { name: "main".to_string(), start: 0, size: 3 },
+            Section { name: "lib".to_string(), start: 3, size: 4 },
+        ]);
+        assert_eq!(linked.section_of(4).map(|section| section.name.as_str()), Some("lib"));
+        assert!(!linked.relocatable && linked.imports.is_empty() && linked.relocations.is_empty());
+    }
+
+    #[test]
+    fn keeps_local_labels_apart() {
+        let linked = link(&[("main".to_string(), program()), ("lib".to_string(), library())]).unwrap();
+        assert_eq!(linked.address_of("main::loop"), Some(1));
+        assert_eq!(linked.address_of("lib::loop"), Some(4));
+        assert_eq!(linked.address_of("loop"), None);
+        assert_eq!(linked.address_of("print"), Some(3));
+    }
+
+    #[test]
+    fn reports_missing_and_duplicate_symbols() {
+        assert_eq!(link(&[("main".to_string(), program())]), Err(LinkError::MissingSymbol { symbol: "print".to_string(), object: "main".to_string() }));
+        let objects = [("main".to_string(), program()), ("lib".to_string(), library()), ("copy".to_string(), library())];
+        assert_eq!(link(&objects), Err(LinkError::DuplicateSymbol { symbol: "print".to_string(), first: "lib".to_string(), second: "copy".to_string() }));
+        assert_eq!(link(&[]), Err(LinkError::NothingToLink));
+    }
+
+    #[test]
+    fn reports_objects_that_cannot_be_placed() {
+        let fixed = ObjectFile { relocatable: false, ..library() };
+        assert_eq!(link(&[("main".to_string(), program()), ("lib".to_string(), fixed)]), Err(LinkError::NotRelocatable("lib".to_string())));
+        let exporting = object("export missing\n0000: 0");
+        assert_eq!(link(&[("x".to_string(), exporting)]), Err(LinkError::UndefinedExport { symbol: "missing".to_string(), object: "x".to_string() }));
+        let large = object("0000: 0\n9999: 0");
+        assert_eq!(link(&[("main".to_string(), program()), ("large".to_string(), large)]), Err(LinkError::TooLarge { object: "large".to_string(), end: 10_003 }));
+    }
+}
@@ -0,0 +1,92 @@
+//! Memory images in the listing format dasm prints:
+//!
+//! ```text
+//! 0000: 1112000005
+//! 0001: 7200000000
+//! 0002: 9900000000
+//! ```
+//!
+//! The address may be left out, in which case the word goes to the address after the previous one.
+//! Blank lines and comments after `|` are ignored.
+
+use std::fmt::Formatter;
+
+const MEMORY_SIZE: usize = 10_000;
+
+/// Turns a listing into `(address, word)` pairs, in the order they are listed.
+pub fn read_listing(listing: &str) -> Result<Vec<(usize, isize)>, ListingError> {
+    let mut image = Vec::new();
+    let mut listed_on = vec![None; MEMORY_SIZE];
+    let mut next_address = 0;
+    for (index, line) in listing.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.split('|').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let malformed = || ListingError::Malformed { line: line_number, text: line.to_string() };
+        let (address, value) = match line.split_once(':') {
+            Some((address, value)) => (address.trim().parse().map_err(|_| malformed())?, value.trim()),
+            None => (next_address, line),
+        };
+        let value = value.parse().map_err(|_| malformed())?;
+        if address >= MEMORY_SIZE {
+            return Err(ListingError::AddressOutOfRange { line: line_number, address });
+        }
+        if let Some(first_line) = listed_on[address] {
+            return Err(ListingError::Overlap { line: line_number, address, first_line });
+        }
+        listed_on[address] = Some(line_number);
+        image.push((address, value));
+        next_address = address + 1;
+    }
+    Ok(image)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ListingError {
+    Malformed { line: usize, text: String },
+    AddressOutOfRange { line: usize, address: usize },
+    /// The address was already listed on `first_line`.
+    Overlap { line: usize, address: usize, first_line: usize },
+}
+
+impl ListingError {
+    pub fn get_line(&self) -> Option<usize> {
+        match self {
+            ListingError::Malformed { line, .. } => Some(*line),
+            ListingError::AddressOutOfRange { line, .. } => Some(*line),
+            ListingError::Overlap { line, .. } => Some(*line),
+        }
+    }
+}
+
+impl std::error::Error for ListingError {}
+
+impl std::fmt::Display for ListingError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ListingError::Malformed { line, text } => write!(f, "Line {}: `{}` is not a word, optionally preceded by `address:`", line, text),
+            ListingError::AddressOutOfRange { line, address } => write!(f, "Line {}: address {} is outside of memory", line, address),
+            ListingError::Overlap { line, address, first_line } => write!(f, "Line {}: address {:04} was already listed on line {}", line, address, first_line),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn addresses_may_be_left_out() {
+        let listing = "| a program\n0010: 1112000005\n7200000000\n\n0002: -3 | data\n";
+        assert_eq!(read_listing(listing), Ok(vec![(10, 1112000005), (11, 7200000000), (2, -3)]));
+    }
+
+    #[test]
+    fn bad_lines_are_reported_with_their_number() {
+        assert_eq!(read_listing("0001: 5\nfive"), Err(ListingError::Malformed { line: 2, text: "five".to_string() }));
+        assert_eq!(read_listing("9999: 1\n2"), Err(ListingError::AddressOutOfRange { line: 2, address: 10_000 }));
+        assert_eq!(read_listing("0003: 1\n\n0002: 1\n2"), Err(ListingError::Overlap { line: 4, address: 3, first_line: 1 }));
+    }
+}
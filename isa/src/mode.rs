@@ -0,0 +1,129 @@
+/// The first modus digit: how the (indexed) operand is interpreted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Mode1 {
+    /// `.w`: the operand is the value itself.
+    Value = 1,
+    /// `.a`: the operand is an address, wrapped into the range of RAM.
+    Address = 2,
+    /// `.d`: the value is read from the address in the operand.
+    Direct = 3,
+    /// `.i`: the value is read from the address stored at the address in the operand.
+    Indirect = 4,
+}
+
+impl Mode1 {
+    pub fn from_digit(digit: usize) -> Option<Self> {
+        match digit {
+            1 => Some(Mode1::Value),
+            2 => Some(Mode1::Address),
+            3 => Some(Mode1::Direct),
+            4 => Some(Mode1::Indirect),
+            _ => None,
+        }
+    }
+
+    pub fn digit(self) -> usize {
+        self as usize
+    }
+
+    /// The interpretation suffix used in assembly (`w`, `a`, `d` or `i`).
+    pub fn interpretation(self) -> char {
+        match self {
+            Mode1::Value => 'w',
+            Mode1::Address => 'a',
+            Mode1::Direct => 'd',
+            Mode1::Indirect => 'i',
+        }
+    }
+
+    pub fn from_interpretation(interpretation: char) -> Option<Self> {
+        match interpretation.to_ascii_lowercase() {
+            'w' => Some(Mode1::Value),
+            'a' => Some(Mode1::Address),
+            'd' => Some(Mode1::Direct),
+            'i' => Some(Mode1::Indirect),
+            _ => None,
+        }
+    }
+
+    /// One level less indirect, as used by instructions whose operand is an address.
+    /// Returns `None` for [`Mode1::Value`], which has nothing less indirect.
+    pub fn less_indirect(self) -> Option<Self> {
+        Mode1::from_digit(self.digit() - 1)
+    }
+
+    /// One level more indirect; the inverse of [`Mode1::less_indirect`].
+    pub fn more_indirect(self) -> Option<Self> {
+        Mode1::from_digit(self.digit() + 1)
+    }
+}
+
+/// The second modus digit: whether and how the index register is applied to the operand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Mode2 {
+    /// `n`: the index register is not used.
+    NoIndex = 1,
+    /// `n(Rx)`: the index register is added to the operand.
+    Index = 2,
+    /// `n(+Rx)`: the index register is incremented, then added.
+    PreIncrement = 3,
+    /// `n(Rx+)`: the index register is added, then incremented.
+    PostIncrement = 4,
+    /// `n(-Rx)`: the index register is decremented, then added.
+    PreDecrement = 5,
+    /// `n(Rx-)`: the index register is added, then decremented.
+    PostDecrement = 6,
+}
+
+impl Mode2 {
+    pub fn from_digit(digit: usize) -> Option<Self> {
+        match digit {
+            1 => Some(Mode2::NoIndex),
+            2 => Some(Mode2::Index),
+            3 => Some(Mode2::PreIncrement),
+            4 => Some(Mode2::PostIncrement),
+            5 => Some(Mode2::PreDecrement),
+            6 => Some(Mode2::PostDecrement),
+            _ => None,
+        }
+    }
+
+    pub fn digit(self) -> usize {
+        self as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mode1_digits_and_interpretations_round_trip() {
+        for digit in 1..=4 {
+            let mode1 = Mode1::from_digit(digit).unwrap();
+            assert_eq!(mode1.digit(), digit);
+            assert_eq!(Mode1::from_interpretation(mode1.interpretation()), Some(mode1));
+            assert_eq!(Mode1::from_interpretation(mode1.interpretation().to_ascii_uppercase()), Some(mode1));
+        }
+        assert_eq!(Mode1::from_digit(0), None);
+        assert_eq!(Mode1::from_digit(5), None);
+        assert_eq!(Mode1::from_interpretation('x'), None);
+    }
+
+    #[test]
+    fn mode1_indirection() {
+        assert_eq!(Mode1::Value.less_indirect(), None);
+        assert_eq!(Mode1::Direct.less_indirect(), Some(Mode1::Address));
+        assert_eq!(Mode1::Indirect.more_indirect(), None);
+        assert_eq!(Mode1::Address.more_indirect(), Some(Mode1::Direct));
+    }
+
+    #[test]
+    fn mode2_digits_round_trip() {
+        for digit in 1..=6 {
+            assert_eq!(Mode2::from_digit(digit).unwrap().digit(), digit);
+        }
+        assert_eq!(Mode2::from_digit(0), None);
+        assert_eq!(Mode2::from_digit(7), None);
+    }
+}
@@ -0,0 +1,549 @@
+//! The object files dasm writes and the simulator loads: an assembled image together with its labels, its `RESGR`
+//! regions, its entry point and the source line of every word.
+//!
+//! dasm assembles every file as if it starts at address 0, and records which words depend on where the file
+//! ends up and on the labels it imports from other files. The [linker](crate::linker) uses that to place several
+//! objects side by side. An object that imports nothing can be loaded as it is.
+//!
+//! The text form looks like this:
+//!
+//! ```text
+//! DRAMA object v2
+//! relocatable
+//! entry 0000
+//! symbol start 0000
+//! symbol buffer 0003
+//! export start
+//! import print
+//! reserve 0003 10
+//! relocate 0000 operand
+//! relocate 0001 operand print
+//! 0000: 1131100003 line 3
+//! 0001: 4121000000 line 4
+//! 0002: 9911000000 line 5
+//! ```
+//!
+//! A linked object has no `relocatable`, `export`, `import` or `relocate` lines, but tells where each of the
+//! objects it was linked from went, as in `section 0000 13 main.dasm`.
+//!
+//! Blank lines and comments after `|` are ignored. The binary form holds the same, see [`ObjectFile::to_bytes`].
+
+use std::convert::TryInto;
+use std::fmt::Formatter;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::word;
+
+/// The format version written by this crate. Older versions are read as long as they are supported.
+pub const OBJECT_VERSION: u32 = 2;
+/// Version 1 lacks everything to do with linking.
+const OLDEST_VERSION: u32 = 1;
+
+const HEADER: &str = "DRAMA object v";
+/// The first bytes of the binary form.
+pub const BINARY_MAGIC: &[u8; 8] = b"DRAMAOBJ";
+const MEMORY_SIZE: usize = 10_000;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ObjectFile {
+    /// Where execution starts.
+    pub entry_point: usize,
+    pub words: Vec<AssembledWord>,
+    /// Every label with its address, ordered by address.
+    pub symbols: Vec<(String, usize)>,
+    /// `(start, length)` of every region set aside with `RESGR`.
+    pub reserved: Vec<(usize, usize)>,
+    /// Whether the linker may move the words elsewhere. Linked objects and those of version 1 are not.
+    pub relocatable: bool,
+    /// The labels other objects may import.
+    pub exports: Vec<String>,
+    /// The labels this object uses but another object defines.
+    pub imports: Vec<String>,
+    /// The words to adjust once the object is placed and its imports are known.
+    pub relocations: Vec<Relocation>,
+    /// Where each of the objects this one was linked from was placed.
+    pub sections: Vec<Section>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AssembledWord {
+    pub address: usize,
+    pub value: isize,
+    /// The source line the word was assembled from, starting at 1.
+    pub line: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Relocation {
+    pub address: usize,
+    pub kind: RelocationKind,
+    /// The imported label whose address is added, or `None` for the address the object is placed at.
+    pub symbol: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RelocationKind {
+    /// Add to the operand of an instruction, wrapping around within its four digits.
+    Operand,
+    /// Add to the whole word, which holds a number.
+    Word,
+}
+
+impl Relocation {
+    /// Adds `value` to `word` as [`kind`](Relocation::kind) says.
+    pub fn apply(&self, word: isize, value: usize) -> isize {
+        match self.kind {
+            RelocationKind::Operand => {
+                let operand = word.rem_euclid(word::OPERAND_MODULUS);
+                word - operand + (operand + value as isize) % word::OPERAND_MODULUS
+            }
+            RelocationKind::Word => word::wrap(word as i128 + value as i128),
+        }
+    }
+}
+
+impl RelocationKind {
+    fn name(self) -> &'static str {
+        match self {
+            RelocationKind::Operand => "operand",
+            RelocationKind::Word => "word",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "operand" => Some(RelocationKind::Operand),
+            "word" => Some(RelocationKind::Word),
+            _ => None,
+        }
+    }
+}
+
+/// The addresses one object went to when it was linked.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Section {
+    /// The name the object was given to the linker by, usually its file name.
+    pub name: String,
+    pub start: usize,
+    pub size: usize,
+}
+
+impl ObjectFile {
+    /// The words as `(address, word)` pairs, ready to be loaded.
+    pub fn image(&self) -> Vec<(usize, isize)> {
+        self.words.iter().map(|word| (word.address, word.value)).collect()
+    }
+
+    pub fn address_of(&self, symbol: &str) -> Option<usize> {
+        self.symbols.iter().find(|(name, _)| name == symbol).map(|&(_, address)| address)
+    }
+
+    /// The number of addresses the object takes up, from 0 up to its last word, `RESGR` region or label.
+    pub fn size(&self) -> usize {
+        let words = self.words.iter().map(|word| word.address + 1);
+        let reserved = self.reserved.iter().map(|&(start, length)| start + length);
+        let symbols = self.symbols.iter().map(|&(_, address)| address);
+        words.chain(reserved).chain(symbols).max().unwrap_or(0)
+    }
+
+    /// The section `address` was linked from, if the object was linked.
+    pub fn section_of(&self, address: usize) -> Option<&Section> {
+        self.sections.iter().find(|section| section.start <= address && address < section.start + section.size)
+    }
+
+    /// Reads an object file in either form.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ObjectError> {
+        ObjectFile::from_bytes(&fs::read(path).map_err(ObjectError::Io)?)
+    }
+
+    /// Reads either form, telling them apart by the [`BINARY_MAGIC`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ObjectError> {
+        if !bytes.starts_with(BINARY_MAGIC) {
+            return std::str::from_utf8(bytes).map_err(|_| ObjectError::MissingHeader)?.parse();
+        }
+        let mut reader = Reader { bytes: &bytes[BINARY_MAGIC.len()..] };
+        let version = reader.u16()? as u32;
+        if !(OLDEST_VERSION..=OBJECT_VERSION).contains(&version) {
+            return Err(ObjectError::UnsupportedVersion(version.to_string()));
+        }
+
+        let mut object = ObjectFile { entry_point: reader.address()?, ..ObjectFile::default() };
+        for _ in 0..reader.u32()? {
+            let name = reader.name()?;
+            object.symbols.push((name, reader.address()?));
+        }
+        for _ in 0..reader.u32()? {
+            let (start, length) = (reader.address()?, reader.u16()? as usize);
+            object.reserved.push((start, length));
+        }
+        for _ in 0..reader.u32()? {
+            let address = reader.address()?;
+            let value = i64::from_le_bytes(reader.take(8)?.try_into().unwrap()) as isize;
+            let line = match reader.u32()? {
+                0 => None,
+                line => Some(line as usize),
+            };
+            object.words.push(AssembledWord { address, value, line });
+        }
+        if version >= 2 {
+            object.relocatable = reader.u8()? != 0;
+            for _ in 0..reader.u32()? {
+                object.exports.push(reader.name()?);
+            }
+            for _ in 0..reader.u32()? {
+                object.imports.push(reader.name()?);
+            }
+            for _ in 0..reader.u32()? {
+                let address = reader.address()?;
+                let kind = match reader.u8()? {
+                    0 => RelocationKind::Operand,
+                    1 => RelocationKind::Word,
+                    _ => return Err(ObjectError::InvalidRelocation),
+                };
+                let symbol = Some(reader.name()?).filter(|name| !name.is_empty());
+                object.relocations.push(Relocation { address, kind, symbol });
+            }
+            for _ in 0..reader.u32()? {
+                let name = reader.name()?;
+                let (start, size) = (reader.address()?, reader.u16()? as usize);
+                object.sections.push(Section { name, start, size });
+            }
+        }
+        object.check_reserved()?;
+        Ok(object)
+    }
+
+    /// The binary form: [`BINARY_MAGIC`], then little-endian fields.
+    ///
+    /// ```text
+    /// u16 version, u16 entry point
+    /// u32 count, then per symbol:   u16 name length, UTF-8 name, u16 address
+    /// u32 count, then per region:   u16 start, u16 length
+    /// u32 count, then per word:     u16 address, i64 value, u32 source line or 0
+    /// u8 relocatable
+    /// u32 count, then per export:   name
+    /// u32 count, then per import:   name
+    /// u32 count, then per relocation: u16 address, u8 kind (0 operand, 1 word), name or empty for the base
+    /// u32 count, then per section:  name, u16 start, u16 size
+    /// ```
+    ///
+    /// where every name is a u16 length followed by UTF-8. Version 1 ends after the words.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        fn name(bytes: &mut Vec<u8>, name: &str) {
+            bytes.extend(&(name.len() as u16).to_le_bytes());
+            bytes.extend(name.as_bytes());
+        }
+
+        let mut bytes = BINARY_MAGIC.to_vec();
+        bytes.extend(&(OBJECT_VERSION as u16).to_le_bytes());
+        bytes.extend(&(self.entry_point as u16).to_le_bytes());
+        bytes.extend(&(self.symbols.len() as u32).to_le_bytes());
+        for (symbol, address) in self.symbols.iter() {
+            name(&mut bytes, symbol);
+            bytes.extend(&(*address as u16).to_le_bytes());
+        }
+        bytes.extend(&(self.reserved.len() as u32).to_le_bytes());
+        for &(start, length) in self.reserved.iter() {
+            bytes.extend(&(start as u16).to_le_bytes());
+            bytes.extend(&(length as u16).to_le_bytes());
+        }
+        bytes.extend(&(self.words.len() as u32).to_le_bytes());
+        for word in self.words.iter() {
+            bytes.extend(&(word.address as u16).to_le_bytes());
+            bytes.extend(&(word.value as i64).to_le_bytes());
+            bytes.extend(&(word.line.unwrap_or(0) as u32).to_le_bytes());
+        }
+        bytes.push(self.relocatable as u8);
+        bytes.extend(&(self.exports.len() as u32).to_le_bytes());
+        for export in self.exports.iter() {
+            name(&mut bytes, export);
+        }
+        bytes.extend(&(self.imports.len() as u32).to_le_bytes());
+        for import in self.imports.iter() {
+            name(&mut bytes, import);
+        }
+        bytes.extend(&(self.relocations.len() as u32).to_le_bytes());
+        for relocation in self.relocations.iter() {
+            bytes.extend(&(relocation.address as u16).to_le_bytes());
+            bytes.push(relocation.kind as u8);
+            name(&mut bytes, relocation.symbol.as_deref().unwrap_or(""));
+        }
+        bytes.extend(&(self.sections.len() as u32).to_le_bytes());
+        for section in self.sections.iter() {
+            name(&mut bytes, &section.name);
+            bytes.extend(&(section.start as u16).to_le_bytes());
+            bytes.extend(&(section.size as u16).to_le_bytes());
+        }
+        bytes
+    }
+
+    fn check_reserved(&self) -> Result<(), ObjectError> {
+        match self.reserved.iter().find(|&&(start, length)| start + length > MEMORY_SIZE) {
+            Some(&(start, length)) => Err(ObjectError::AddressOutOfRange(start + length - 1)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Takes the binary form apart, front to back.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], ObjectError> {
+        if self.bytes.len() < length {
+            return Err(ObjectError::Truncated);
+        }
+        let (taken, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, ObjectError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, ObjectError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, ObjectError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn name(&mut self) -> Result<String, ObjectError> {
+        let length = self.u16()? as usize;
+        String::from_utf8(self.take(length)?.to_vec()).map_err(|_| ObjectError::InvalidName)
+    }
+
+    fn address(&mut self) -> Result<usize, ObjectError> {
+        match self.u16()? as usize {
+            address if address < MEMORY_SIZE => Ok(address),
+            address => Err(ObjectError::AddressOutOfRange(address)),
+        }
+    }
+}
+
+impl std::fmt::Display for ObjectFile {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}{}", HEADER, OBJECT_VERSION)?;
+        if self.relocatable {
+            writeln!(f, "relocatable")?;
+        }
+        writeln!(f, "entry {:04}", self.entry_point)?;
+        for (name, address) in self.symbols.iter() {
+            writeln!(f, "symbol {} {:04}", name, address)?;
+        }
+        for export in self.exports.iter() {
+            writeln!(f, "export {}", export)?;
+        }
+        for import in self.imports.iter() {
+            writeln!(f, "import {}", import)?;
+        }
+        for section in self.sections.iter() {
+            writeln!(f, "section {:04} {} {}", section.start, section.size, section.name)?;
+        }
+        for (start, length) in self.reserved.iter() {
+            writeln!(f, "reserve {:04} {}", start, length)?;
+        }
+        for relocation in self.relocations.iter() {
+            write!(f, "relocate {:04} {}", relocation.address, relocation.kind.name())?;
+            match &relocation.symbol {
+                Some(symbol) => writeln!(f, " {}", symbol)?,
+                None => writeln!(f)?,
+            }
+        }
+        for word in self.words.iter() {
+            write!(f, "{:04}: {:010}", word.address, word.value)?;
+            match word.line {
+                Some(line) => writeln!(f, " line {}", line)?,
+                None => writeln!(f)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for ObjectFile {
+    type Err = ObjectError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lines = s.lines().enumerate()
+            .map(|(index, line)| (index + 1, line.split('|').next().unwrap().trim()))
+            .filter(|(_, line)| !line.is_empty());
+
+        let version = match lines.next() {
+            Some((_, line)) if line.starts_with(HEADER) => &line[HEADER.len()..],
+            _ => return Err(ObjectError::MissingHeader),
+        };
+        match version.parse() {
+            Ok(version) if (OLDEST_VERSION..=OBJECT_VERSION).contains(&version) => {}
+            _ => return Err(ObjectError::UnsupportedVersion(version.to_string())),
+        }
+
+        let mut object = ObjectFile::default();
+        for (line_number, line) in lines {
+            let malformed = || ObjectError::Malformed { line: line_number, text: line.to_string() };
+            let address = |text: &str| match text.parse() {
+                Ok(address) if address < MEMORY_SIZE => Ok(address),
+                Ok(address) => Err(ObjectError::AddressOutOfRange(address)),
+                Err(_) => Err(malformed()),
+            };
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            match parts.as_slice() {
+                ["entry", entry_point] => object.entry_point = address(entry_point)?,
+                ["symbol", name, symbol_address] => object.symbols.push((name.to_string(), address(symbol_address)?)),
+                ["reserve", start, length] => object.reserved.push((address(start)?, length.parse().map_err(|_| malformed())?)),
+                ["relocatable"] => object.relocatable = true,
+                ["export", name] => object.exports.push(name.to_string()),
+                ["import", name] => object.imports.push(name.to_string()),
+                ["relocate", relocation_address, kind, symbol @ ..] if symbol.len() <= 1 => object.relocations.push(Relocation {
+                    address: address(relocation_address)?,
+                    kind: RelocationKind::from_name(kind).ok_or_else(malformed)?,
+                    symbol: symbol.first().map(|symbol| symbol.to_string()),
+                }),
+                ["section", start, size, name @ ..] if !name.is_empty() => object.sections.push(Section {
+                    name: name.join(" "),
+                    start: address(start)?,
+                    size: size.parse().map_err(|_| malformed())?,
+                }),
+                [word_address, value, rest @ ..] if word_address.ends_with(':') => {
+                    let line = match rest {
+                        [] => None,
+                        ["line", line] => Some(line.parse().map_err(|_| malformed())?),
+                        _ => return Err(malformed()),
+                    };
+                    object.words.push(AssembledWord {
+                        address: address(word_address.trim_end_matches(':'))?,
+                        value: value.parse().map_err(|_| malformed())?,
+                        line,
+                    });
+                }
+                _ => return Err(malformed()),
+            }
+        }
+        object.check_reserved()?;
+        Ok(object)
+    }
+}
+
+#[derive(Debug)]
+pub enum ObjectError {
+    Io(io::Error),
+    MissingHeader,
+    UnsupportedVersion(String),
+    Malformed { line: usize, text: String },
+    /// The binary form ended in the middle of a field.
+    Truncated,
+    /// A name in the binary form is not UTF-8.
+    InvalidName,
+    /// A relocation in the binary form is of an unknown kind.
+    InvalidRelocation,
+    AddressOutOfRange(usize),
+}
+
+impl ObjectError {
+    pub fn get_line(&self) -> Option<usize> {
+        match self {
+            ObjectError::Malformed { line, .. } => Some(*line),
+            _ => None,
+        }
+    }
+}
+
+impl std::error::Error for ObjectError {}
+
+impl std::fmt::Display for ObjectError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ObjectError::Io(error) => write!(f, "Could not read the object file: {}", error),
+            ObjectError::MissingHeader => write!(f, "Not an object file: it should start with `{}{}` or the binary magic", HEADER, OBJECT_VERSION),
+            ObjectError::UnsupportedVersion(version) => write!(f, "Object file version `{}` is not supported, only versions {} to {} are", version, OLDEST_VERSION, OBJECT_VERSION),
+            ObjectError::Malformed { line, text } => write!(f, "Line {}: `{}` is not understood", line, text),
+            ObjectError::Truncated => write!(f, "The object file ends too early"),
+            ObjectError::InvalidName => write!(f, "A name is not valid UTF-8"),
+            ObjectError::InvalidRelocation => write!(f, "A relocation is of an unknown kind"),
+            ObjectError::AddressOutOfRange(address) => write!(f, "Address {} is outside of memory", address),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example() -> ObjectFile {
+        ObjectFile {
+            entry_point: 1,
+            words: vec![
+                AssembledWord { address: 0, value: -17, line: None },
+                AssembledWord { address: 1, value: 1131100003, line: Some(3) },
+                AssembledWord { address: 2, value: 9911000000, line: Some(12) },
+            ],
+            symbols: vec![("data".to_string(), 0), ("start".to_string(), 1), ("buffer".to_string(), 3)],
+            reserved: vec![(3, 10), (9990, 10)],
+            ..ObjectFile::default()
+        }
+    }
+
+    #[test]
+    fn text_form_round_trips() {
+        let object = example();
+        let text = object.to_string();
+        assert!(text.starts_with("DRAMA object v2\n"));
+        assert_eq!(text.parse::<ObjectFile>().unwrap(), object);
+        assert_eq!(ObjectFile::from_bytes(text.as_bytes()).unwrap(), object);
+    }
+
+    #[test]
+    fn binary_form_round_trips() {
+        let object = example();
+        let bytes = object.to_bytes();
+        assert!(bytes.starts_with(BINARY_MAGIC));
+        assert_eq!(ObjectFile::from_bytes(&bytes).unwrap(), object);
+    }
+
+    #[test]
+    fn reads_version_1() {
+        let object: ObjectFile = "DRAMA object v1\nentry 0001 | comment\n\nsymbol start 0001\nreserve 0003 10\n0001: 1131100003 line 3\n"
+            .parse().unwrap();
+        assert_eq!(object.entry_point, 1);
+        assert_eq!(object.symbols, vec![("start".to_string(), 1)]);
+        assert_eq!(object.reserved, vec![(3, 10)]);
+        assert_eq!(object.words, vec![AssembledWord { address: 1, value: 1131100003, line: Some(3) }]);
+        assert!(!object.relocatable);
+
+        let mut bytes = BINARY_MAGIC.to_vec();
+        bytes.extend(&[1, 0, 1, 0]);
+        bytes.extend(&[0; 12]);
+        assert_eq!(ObjectFile::from_bytes(&bytes).unwrap(), ObjectFile { entry_point: 1, ..ObjectFile::default() });
+    }
+
+    #[test]
+    fn rejects_unknown_versions() {
+        let text = example().to_string().replacen("v2", "v3", 1);
+        assert!(matches!(text.parse::<ObjectFile>(), Err(ObjectError::UnsupportedVersion(version)) if version == "3"));
+        assert!(matches!("DRAMA object v0\n".parse::<ObjectFile>(), Err(ObjectError::UnsupportedVersion(_))));
+        assert!(matches!("DRAMA object vx\n".parse::<ObjectFile>(), Err(ObjectError::UnsupportedVersion(_))));
+
+        let mut bytes = example().to_bytes();
+        bytes[BINARY_MAGIC.len()] = 3;
+        assert!(matches!(ObjectFile::from_bytes(&bytes), Err(ObjectError::UnsupportedVersion(version)) if version == "3"));
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        assert!(matches!("".parse::<ObjectFile>(), Err(ObjectError::MissingHeader)));
+        let error = "DRAMA object v2\nentry 0000\nsymbol start\n".parse::<ObjectFile>().unwrap_err();
+        assert_eq!(error.get_line(), Some(3));
+        assert!(matches!("DRAMA object v2\nreserve 9995 10\n".parse::<ObjectFile>(), Err(ObjectError::AddressOutOfRange(10_004))));
+        assert!(matches!("DRAMA object v2\n10000: 0\n".parse::<ObjectFile>(), Err(ObjectError::AddressOutOfRange(10_000))));
+
+        let bytes = example().to_bytes();
+        assert!(matches!(ObjectFile::from_bytes(&bytes[..bytes.len() - 1]), Err(ObjectError::Truncated)));
+    }
+}
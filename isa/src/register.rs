@@ -0,0 +1,47 @@
+use std::fmt::{Display, Formatter};
+
+/// One of the ten accumulators, `R0` through `R9`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Register(u8);
+
+impl Register {
+    pub const R0: Register = Register(0);
+    pub const R1: Register = Register(1);
+    pub const R2: Register = Register(2);
+    pub const R3: Register = Register(3);
+    pub const R4: Register = Register(4);
+    pub const R5: Register = Register(5);
+    pub const R6: Register = Register(6);
+    pub const R7: Register = Register(7);
+    pub const R8: Register = Register(8);
+    /// `R9` is the stack pointer used by `SBR` and `KTG`.
+    pub const R9: Register = Register(9);
+
+    pub fn new(index: usize) -> Option<Self> {
+        if index < 10 {
+            Some(Register(index as u8))
+        } else {
+            None
+        }
+    }
+
+    /// Parses a register in the form `Rx`, where `0 <= x <= 9`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let digit = name.strip_prefix('R')?;
+        // Anything but a single digit is longer than 2 characters or fails to parse.
+        if digit.len() != 1 {
+            return None;
+        }
+        Register::new(digit.parse().ok()?)
+    }
+
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl Display for Register {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "R{}", self.0)
+    }
+}
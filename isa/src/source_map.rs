@@ -0,0 +1,155 @@
+//! Which source line each address was assembled from, and where the labels are, as written by `dasm --source-map`.
+//!
+//! ```text
+//! DRAMA source map v1
+//! label start 0000
+//! 0000 3
+//! 0001 4
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt::Formatter;
+use std::str::FromStr;
+
+use crate::object::ObjectFile;
+
+const HEADER: &str = "DRAMA source map v";
+pub const SOURCE_MAP_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceMap {
+    lines: BTreeMap<usize, usize>,
+    labels: BTreeMap<String, usize>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        SourceMap::default()
+    }
+
+    pub fn insert(&mut self, address: usize, line_number: usize) {
+        self.lines.insert(address, line_number);
+    }
+
+    /// The line the word at `address` was assembled from. Line numbers start at 1.
+    pub fn line_of(&self, address: usize) -> Option<usize> {
+        self.lines.get(&address).copied()
+    }
+
+    /// Every mapped address with its line, in order of address.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.lines.iter().map(|(&address, &line_number)| (address, line_number))
+    }
+
+    pub fn insert_label(&mut self, label: &str, address: usize) {
+        self.labels.insert(label.to_string(), address);
+    }
+
+    pub fn address_of(&self, label: &str) -> Option<usize> {
+        self.labels.get(label).copied()
+    }
+
+    /// Names an address after the closest label at or before it, as in `loop+2`.
+    /// Falls back to the bare address when there is no such label.
+    pub fn symbolize(&self, address: usize) -> String {
+        let closest = self.labels.iter()
+            .filter(|&(_, &label_address)| label_address <= address)
+            .max_by_key(|&(label, &label_address)| (label_address, std::cmp::Reverse(label)));
+        match closest {
+            Some((label, &label_address)) if label_address == address => label.clone(),
+            Some((label, &label_address)) => format!("{}+{}", label, address - label_address),
+            None => format!("{:04}", address),
+        }
+    }
+}
+
+impl From<&ObjectFile> for SourceMap {
+    fn from(object: &ObjectFile) -> Self {
+        let mut source_map = SourceMap::new();
+        for word in object.words.iter() {
+            if let Some(line_number) = word.line {
+                source_map.insert(word.address, line_number);
+            }
+        }
+        for (label, address) in object.symbols.iter() {
+            source_map.insert_label(label, *address);
+        }
+        source_map
+    }
+}
+
+impl std::fmt::Display for SourceMap {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}{}", HEADER, SOURCE_MAP_VERSION)?;
+        for (label, address) in self.labels.iter() {
+            writeln!(f, "label {} {:04}", label, address)?;
+        }
+        for (address, line_number) in self.iter() {
+            writeln!(f, "{:04} {}", address, line_number)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for SourceMap {
+    type Err = SourceMapError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lines = s.lines().enumerate()
+            .map(|(index, line)| (index + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty());
+
+        let version = match lines.next() {
+            Some((_, line)) if line.starts_with(HEADER) => &line[HEADER.len()..],
+            _ => return Err(SourceMapError::MissingHeader),
+        };
+        if version.parse() != Ok(SOURCE_MAP_VERSION) {
+            return Err(SourceMapError::UnsupportedVersion(version.to_string()));
+        }
+
+        let mut source_map = SourceMap::new();
+        for (line_number, line) in lines {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            match parts.as_slice() {
+                ["label", label, address] => match address.parse() {
+                    Ok(address) => source_map.insert_label(label, address),
+                    Err(_) => return Err(SourceMapError::Malformed { line: line_number, text: line.to_string() }),
+                },
+                [address, source_line] => match (address.parse(), source_line.parse()) {
+                    (Ok(address), Ok(source_line)) => source_map.insert(address, source_line),
+                    _ => return Err(SourceMapError::Malformed { line: line_number, text: line.to_string() }),
+                },
+                _ => return Err(SourceMapError::Malformed { line: line_number, text: line.to_string() }),
+            }
+        }
+        Ok(source_map)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SourceMapError {
+    MissingHeader,
+    UnsupportedVersion(String),
+    Malformed { line: usize, text: String },
+}
+
+impl SourceMapError {
+    pub fn get_line(&self) -> Option<usize> {
+        match self {
+            SourceMapError::Malformed { line, .. } => Some(*line),
+            _ => None,
+        }
+    }
+}
+
+impl std::error::Error for SourceMapError {}
+
+impl std::fmt::Display for SourceMapError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SourceMapError::MissingHeader => write!(f, "Not a source map: the first line should be `{}{}`", HEADER, SOURCE_MAP_VERSION),
+            SourceMapError::UnsupportedVersion(version) => write!(f, "Source map version `{}` is not supported, only version {} is", version, SOURCE_MAP_VERSION),
+            SourceMapError::Malformed { line, text } => write!(f, "Line {}: `{}` is neither `label NAME ADDRESS` nor an address followed by a line number", line, text),
+        }
+    }
+}
@@ -0,0 +1,164 @@
+//! The layout of a 10-digit instruction word.
+//!
+//! ```text
+//! 01_23_4_5_6789
+//! fc_mo_a_i_operand
+//! ```
+//!
+//! The operand is stored in ten's complement: `5000` through `9999` represent `-5000` through `-1`.
+//!
+//! Strings, as printed by `DRS`, are laid out one character per word; see [`encode_string`].
+
+use std::convert::TryFrom;
+
+use crate::{FunctionCode, Mode1, Mode2, Register};
+
+/// The number of distinct values of a word, `10^10`.
+pub const WORD_MODULUS: isize = 10_000_000_000;
+/// The smallest value a word holds: `5_000_000_000` in ten's complement.
+pub const WORD_MIN: isize = -WORD_MODULUS / 2;
+/// The largest value a word holds.
+pub const WORD_MAX: isize = WORD_MODULUS / 2 - 1;
+/// The number of distinct values of an operand, `10^4`.
+pub const OPERAND_MODULUS: isize = 10_000;
+
+/// The raw digit groups of an instruction word, without any validation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fields {
+    pub fc: usize,
+    pub mode1: usize,
+    pub mode2: usize,
+    pub acc: usize,
+    pub index: usize,
+    /// The sign-extended operand, in `-5000..5000`.
+    pub operand: isize,
+}
+
+macro_rules! num_range {
+    ($c:expr, $s:expr; $e:expr) => {
+        {
+            const DIV: usize = 10usize.pow(10 - $e);
+            const MOD: usize = 10usize.pow($e - $s);
+            ($c / DIV) % MOD
+        }
+    };
+}
+
+/// Splits a word into its digit groups.
+/// Negative words are read in ten's complement, so `-1` is split as `99_99_9_9_9999`.
+pub fn split(word: isize) -> Fields {
+    let command = word.rem_euclid(WORD_MODULUS) as usize;
+    Fields {
+        fc: num_range!(command, 0; 2),
+        mode1: num_range!(command, 2; 3),
+        mode2: num_range!(command, 3; 4),
+        acc: num_range!(command, 4; 5),
+        index: num_range!(command, 5; 6),
+        operand: sign_extend_operand(num_range!(command, 6; 10) as isize),
+    }
+}
+
+/// Assembles an instruction word. The operand is wrapped into the 4-digit operand field.
+#[allow(clippy::inconsistent_digit_grouping)]
+pub fn encode(fc: FunctionCode, mode1: Mode1, mode2: Mode2, acc: Register, index: Register, operand: isize) -> isize {
+    let o = operand.rem_euclid(OPERAND_MODULUS);
+    (fc.code() as isize) * 1_00_0_0_0000
+        + (mode1.digit() as isize) * 10_0_0_0000
+        + (mode2.digit() as isize) * 10_0_0000
+        + (acc.index() as isize) * 1_0_0000
+        + (index.index() as isize) * 1_0000
+        + o
+}
+
+/// Assembles an instruction that takes no operand, such as `STP`.
+/// All other fields are filled in canonically, so that the word decodes without errors.
+pub fn encode_without_operand(fc: FunctionCode) -> isize {
+    encode(fc, Mode1::Value, Mode2::NoIndex, Register::R0, Register::R0, 0)
+}
+
+/// Whether a value fits in a word without wrapping.
+pub fn fits(value: i128) -> bool {
+    (WORD_MIN as i128..=WORD_MAX as i128).contains(&value)
+}
+
+/// Wraps any value into the range of a word, as ten's complement arithmetic on 10 digits does.
+pub fn wrap(value: i128) -> isize {
+    let modulus = WORD_MODULUS as i128;
+    ((value - WORD_MIN as i128).rem_euclid(modulus) + WORD_MIN as i128) as isize
+}
+
+/// Reads a 4-digit operand field in ten's complement.
+pub fn sign_extend_operand(field: isize) -> isize {
+    if field >= OPERAND_MODULUS / 2 { field - OPERAND_MODULUS } else { field }
+}
+
+/// Encodes a string the way `DRS` reads it: one word per character, holding its Unicode code point,
+/// followed by a `0` word that terminates the string.
+pub fn encode_string(string: &str) -> Vec<isize> {
+    string.chars()
+        .map(|c| c as isize)
+        .chain(std::iter::once(0))
+        .collect()
+}
+
+/// Reads one character of a string. Returns `None` for the terminator and for words that aren't a code point.
+pub fn decode_char(word: isize) -> Option<char> {
+    if word <= 0 {
+        return None;
+    }
+    std::char::from_u32(u32::try_from(word).ok()?)
+}
+
+#[cfg(test)]
+#[allow(clippy::inconsistent_digit_grouping)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_wraps_negative_operands() {
+        let word = encode(FunctionCode::HIA, Mode1::Value, Mode2::NoIndex, Register::R1, Register::R0, -1);
+        assert_eq!(word, 11_11_1_0_9999);
+        assert_eq!(split(word).operand, -1);
+        let word = encode(FunctionCode::HIA, Mode1::Value, Mode2::NoIndex, Register::R1, Register::R0, -5000);
+        assert_eq!(split(word).operand, -5000);
+        assert_eq!(split(encode(FunctionCode::HIA, Mode1::Value, Mode2::NoIndex, Register::R1, Register::R0, 4999)).operand, 4999);
+    }
+
+    #[test]
+    fn split_reads_negative_words_in_tens_complement() {
+        let fields = split(-1);
+        assert_eq!((fields.fc, fields.mode1, fields.mode2, fields.acc, fields.index, fields.operand), (99, 9, 9, 9, 9, -1));
+        let fields = split(WORD_MIN);
+        assert_eq!((fields.fc, fields.mode1, fields.mode2, fields.operand), (50, 0, 0, 0));
+        let fields = split(WORD_MAX);
+        assert_eq!((fields.fc, fields.mode1, fields.mode2, fields.operand), (49, 9, 9, -1));
+        // The raw digits of a word read the same as its ten's complement value
+        assert_eq!(split(WORD_MODULUS - 1), split(-1));
+    }
+
+    #[test]
+    fn wrap_and_fits_agree_at_the_edges() {
+        assert!(fits(WORD_MIN as i128) && fits(WORD_MAX as i128));
+        assert!(!fits(WORD_MIN as i128 - 1) && !fits(WORD_MAX as i128 + 1));
+        assert_eq!(wrap(WORD_MAX as i128 + 1), WORD_MIN);
+        assert_eq!(wrap(WORD_MIN as i128 - 1), WORD_MAX);
+        assert_eq!(wrap(WORD_MODULUS as i128 - 1), -1);
+        assert_eq!(wrap(-7), -7);
+    }
+
+    #[test]
+    fn sign_extends_operands() {
+        assert_eq!(sign_extend_operand(0), 0);
+        assert_eq!(sign_extend_operand(4999), 4999);
+        assert_eq!(sign_extend_operand(5000), -5000);
+        assert_eq!(sign_extend_operand(9999), -1);
+    }
+
+    #[test]
+    fn strings_round_trip() {
+        let words = encode_string("Hé\n");
+        assert_eq!(words, vec![72, 233, 10, 0]);
+        assert_eq!(words.iter().map_while(|&word| decode_char(word)).collect::<String>(), "Hé\n");
+        assert_eq!(decode_char(-65), None);
+    }
+}
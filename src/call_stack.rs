@@ -0,0 +1,172 @@
+//! A shadow of the `R9` stack, to check that subroutines return where they were called from.
+
+use drama_isa::source_map::SourceMap;
+use drama_isa::FunctionCode;
+
+use crate::state::cpu::StepOutcome;
+use crate::state::ram;
+
+/// The subroutine calls that haven't returned yet, as set in [`CPU::call_stack`](crate::state::cpu::CPU::call_stack).
+///
+/// While it is set, the CPU faults when the stack is pushed onto a cell [marked as loaded](crate::state::ram::RAM::mark_loaded),
+/// and when a `KTG` or `KTO` returns anywhere but to the most recent pending call.
+#[derive(Debug, Clone, Default)]
+pub struct CallStack {
+    frames: Vec<Frame>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frame {
+    /// The address of the `SBR`, or of the instruction an interrupt came before.
+    pub call_site: usize,
+    /// The address of the subroutine or interrupt handler.
+    pub entry: usize,
+    pub return_address: usize,
+    /// The line, if the frame is an interrupt handler.
+    pub interrupt: Option<usize>,
+}
+
+impl CallStack {
+    pub fn new() -> Self {
+        CallStack::default()
+    }
+
+    /// The pending calls, outermost first.
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    /// Where the innermost pending call returns to.
+    pub fn expected_return(&self) -> Option<usize> {
+        self.frames.last().map(|frame| frame.return_address)
+    }
+
+    pub fn record(&mut self, outcome: &StepOutcome) {
+        match outcome.instruction.fc {
+            FunctionCode::SBR => {
+                let return_address = match outcome.interrupt {
+                    Some(_) => outcome.address,
+                    None => ram::address(outcome.address as isize + 1),
+                };
+                self.push(Frame {
+                    call_site: outcome.address,
+                    entry: ram::address(outcome.operand),
+                    return_address,
+                    interrupt: outcome.interrupt,
+                });
+            }
+            FunctionCode::KTG | FunctionCode::KTO => {
+                self.pop();
+            }
+            _ => {}
+        }
+    }
+
+    pub(crate) fn push(&mut self, frame: Frame) {
+        self.frames.push(frame);
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<Frame> {
+        self.frames.pop()
+    }
+
+    /// Lists the current instruction and every pending call site, innermost first, named after the closest labels.
+    pub fn backtrace(&self, instruction_pointer: usize, source_map: Option<&SourceMap>) -> String {
+        let describe = |address: usize| {
+            let mut description = format!("{:04}", address);
+            if let Some(source_map) = source_map {
+                description.push_str(&format!(" {}", source_map.symbolize(address)));
+                if let Some(line_number) = source_map.line_of(address) {
+                    description.push_str(&format!(" (line {})", line_number));
+                }
+            }
+            description
+        };
+
+        let mut backtrace = format!("#0 {}\n", describe(instruction_pointer));
+        for (depth, frame) in self.frames.iter().rev().enumerate() {
+            backtrace.push_str(&format!("#{} {}", depth + 1, describe(frame.call_site)));
+            if let Some(line) = frame.interrupt {
+                backtrace.push_str(&format!(" [interrupt {}]", line));
+            }
+            backtrace.push('\n');
+        }
+        backtrace
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use drama_isa::word::{encode, encode_without_operand};
+    use drama_isa::{Mode1, Mode2, Register};
+
+    use super::*;
+    use crate::io::ScriptedIo;
+    use crate::state::cpu::CPU;
+    use crate::state::cpu_fault::{CpuFault, FaultSite};
+    use crate::state::ram::RAM;
+
+    fn machine(image: &[(usize, isize)]) -> (CPU, RAM) {
+        let mut ram = RAM::new();
+        ram.load_image(image).unwrap();
+        let mut cpu = CPU::with_io(Box::new(ScriptedIo::default()));
+        cpu.call_stack = Some(CallStack::new());
+        (cpu, ram)
+    }
+
+    fn call(entry: isize) -> isize {
+        encode(FunctionCode::SBR, Mode1::Address, Mode2::NoIndex, Register::R0, Register::R0, entry)
+    }
+
+    #[test]
+    fn nested_calls_are_pushed_and_popped_in_order() {
+        let ktg = encode_without_operand(FunctionCode::KTG);
+        let (mut cpu, mut ram) = machine(&[(0, call(10)), (1, encode_without_operand(FunctionCode::STP)), (10, call(20)), (11, ktg), (20, ktg)]);
+        cpu.step(&mut ram).unwrap();
+        cpu.step(&mut ram).unwrap();
+        let call_stack = cpu.call_stack.as_ref().unwrap();
+        assert_eq!(call_stack.frames(), &[
+            Frame { call_site: 0, entry: 10, return_address: 1, interrupt: None },
+            Frame { call_site: 10, entry: 20, return_address: 11, interrupt: None },
+        ]);
+        assert_eq!(call_stack.backtrace(20, None), "#0 0020\n#1 0010\n#2 0000\n");
+
+        cpu.step(&mut ram).unwrap();
+        assert_eq!(cpu.call_stack.as_ref().unwrap().expected_return(), Some(1));
+        cpu.step(&mut ram).unwrap();
+        assert!(cpu.call_stack.as_ref().unwrap().frames().is_empty());
+        assert_eq!(cpu.instruction_pointer, 1);
+    }
+
+    #[test]
+    fn pushing_onto_the_program_is_a_stack_collision() {
+        let (mut cpu, mut ram) = machine(&[(0, call(10)), (10, encode_without_operand(FunctionCode::KTG))]);
+        // The return address would go to 10, over the subroutine
+        cpu.accumulators[9] = 11;
+        let fault = cpu.step(&mut ram).unwrap_err();
+        assert_eq!(fault, CpuFault::StackCollision(FaultSite { address: 0, word: call(10) }, 10));
+        assert_eq!(cpu.accumulators[9], 11);
+        assert!(cpu.call_stack.unwrap().frames().is_empty());
+    }
+
+    #[test]
+    fn returning_anywhere_but_to_the_caller_is_a_return_mismatch() {
+        let ktg = encode_without_operand(FunctionCode::KTG);
+        let (mut cpu, mut ram) = machine(&[(0, call(10)), (10, ktg), (20, ktg)]);
+        cpu.accumulators[9] = 100;
+        cpu.step(&mut ram).unwrap();
+        // The subroutine overwrites its return address
+        ram[99usize] = 20;
+        let fault = cpu.step(&mut ram).unwrap_err();
+        assert_eq!(fault, CpuFault::ReturnMismatch { site: FaultSite { address: 10, word: ktg }, expected: Some(1), found: 20 });
+        assert_eq!(cpu.instruction_pointer, 10);
+
+        // A return without a call
+        let (mut cpu, mut ram) = machine(&[(20, ktg)]);
+        cpu.instruction_pointer = 20;
+        cpu.accumulators[9] = 100;
+        ram[100usize] = 5;
+        let fault = cpu.step(&mut ram).unwrap_err();
+        assert_eq!(fault, CpuFault::ReturnMismatch { site: FaultSite { address: 20, word: ktg }, expected: None, found: 5 });
+    }
+}
@@ -0,0 +1,277 @@
+//! `dramasim run`: assembles a program with dasm, or reads an object file, and runs it without a window.
+
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::io::Write;
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::process::Command;
+use std::rc::Rc;
+
+use drama_isa::object::{ObjectFile, BINARY_MAGIC};
+use drama_isa::source_map::SourceMap;
+use drama_sim::call_stack::CallStack;
+use drama_sim::io::{FrontendIo, IoDevice, StdIo};
+use drama_sim::loader;
+use drama_sim::profiler::Profiler;
+use drama_sim::state::cpu::{RunOutcome, CPU};
+use drama_sim::state::ram::RAM;
+use drama_sim::trace::TraceWriter;
+
+pub const EXIT_HALTED: i32 = 0;
+pub const EXIT_FAULT: i32 = 1;
+pub const EXIT_LIMIT: i32 = 2;
+/// Bad arguments, an unreadable file, or a program that doesn't assemble.
+pub const EXIT_ERROR: i32 = 3;
+
+/// How many of the most executed addresses `--profile` lists.
+const PROFILE_HOTTEST: usize = 10;
+
+pub const USAGE: &str = "\
+Usage:
+    dramasim run PROGRAM [OPTIONS]
+    dramasim gui
+
+`run` assembles PROGRAM with dasm and runs it until it stops. PROGRAM may also be an object file
+written by `dasm --object` or `dasm --binary-object`, or a program linked by `dasm --link`.
+
+Options:
+    --input VALUES       Numbers for LEZ to read, separated by commas or spaces, instead of reading stdin
+    --max-steps N        Stop after N instructions
+    --trace FILE         Record every instruction in FILE, as CSV if it ends in .csv and as JSON lines otherwise
+    --dump-registers     Print the registers once the program stops
+    --dump-memory RANGE  Print the cells in RANGE once the program stops, as in 100, 100..110 or 100..=109.
+                         May be given more than once
+    --profile            Print how often each address and subroutine ran once the program stops,
+                         and the source with the count of every line if PROGRAM is a source file
+    --check-calls        Track subroutine calls: fault when the stack runs into the program or a KTG
+                         returns anywhere but to its caller, and print a backtrace on a fault.
+                         Makes the run slower
+
+The program's output goes to stdout, and the reports and messages of dramasim to stderr.
+
+Exit status: 0 if the program halted, 1 if it faulted, 2 if it reached --max-steps,
+and 3 if it couldn't be run at all.
+
+dasm is looked for in $DASM, next to dramasim, and on the PATH, in that order.
+";
+
+struct Options {
+    program: String,
+    input: Option<Vec<isize>>,
+    max_steps: Option<u64>,
+    trace: Option<String>,
+    dump_registers: bool,
+    dump_memory: Vec<RangeInclusive<usize>>,
+    profile: bool,
+    check_calls: bool,
+}
+
+/// Runs `dramasim run` with the arguments after `run`, returning the exit status.
+pub fn run(args: &[String]) -> i32 {
+    let options = match parse_options(args) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}\n\n{}", message, USAGE);
+            return EXIT_ERROR;
+        }
+    };
+    match execute(&options) {
+        Ok(status) => status,
+        Err(message) => {
+            eprintln!("{}", message);
+            EXIT_ERROR
+        }
+    }
+}
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        program: String::new(),
+        input: None,
+        max_steps: None,
+        trace: None,
+        dump_registers: false,
+        dump_memory: Vec::new(),
+        profile: false,
+        check_calls: false,
+    };
+    let mut program = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("`{}` needs a value", arg));
+        match arg.as_str() {
+            "--input" => {
+                let values = value()?;
+                let input = values.split(|c: char| c == ',' || c.is_whitespace())
+                    .filter(|number| !number.is_empty())
+                    .map(|number| number.parse().map_err(|_| format!("`{}` in --input is not an integer", number)))
+                    .collect::<Result<_, _>>()?;
+                options.input = Some(input);
+            }
+            "--max-steps" => {
+                let steps = value()?;
+                options.max_steps = Some(steps.parse().map_err(|_| format!("`{}` is not a number of steps", steps))?);
+            }
+            "--trace" => options.trace = Some(value()?.clone()),
+            "--dump-registers" => options.dump_registers = true,
+            "--dump-memory" => options.dump_memory.push(parse_range(value()?)?),
+            "--profile" => options.profile = true,
+            "--check-calls" => options.check_calls = true,
+            _ if arg.starts_with("--") => return Err(format!("Unknown option `{}`", arg)),
+            _ if program.is_none() => program = Some(arg.clone()),
+            _ => return Err(format!("Only one program can be run, but `{}` was given as well", arg)),
+        }
+    }
+    options.program = program.ok_or("No program given")?;
+    Ok(options)
+}
+
+/// Reads `START`, `START..END` or `START..=END`.
+fn parse_range(range: &str) -> Result<RangeInclusive<usize>, String> {
+    let address = |text: &str| match text.parse() {
+        Ok(address) if address < 10_000 => Ok(address),
+        _ => Err(format!("`{}` in --dump-memory is not an address", text)),
+    };
+    let range = if let Some((start, end)) = range.split_once("..=") {
+        address(start)?..=address(end)?
+    } else if let Some((start, end)) = range.split_once("..") {
+        let end = address(end)?;
+        if end == 0 {
+            return Err(format!("`{}` is an empty range", range));
+        }
+        address(start)?..=end - 1
+    } else {
+        let address = address(range)?;
+        address..=address
+    };
+    if range.is_empty() {
+        return Err(format!("The range `{}..={}` is empty", range.start(), range.end()));
+    }
+    Ok(range)
+}
+
+fn execute(options: &Options) -> Result<i32, String> {
+    let (object, assembled) = read_program(&options.program)?;
+    let source_map = SourceMap::from(&object);
+    let read: Box<dyn FnMut() -> Option<isize>> = match &options.input {
+        Some(input) => {
+            let mut input: VecDeque<isize> = input.iter().copied().collect();
+            Box::new(move || input.pop_front())
+        }
+        None => Box::new(|| StdIo.read_integer()),
+    };
+    // Whether the program's output ends a line, so the reports can start on a line of their own
+    let at_line_start = Rc::new(Cell::new(true));
+    let write = {
+        let at_line_start = at_line_start.clone();
+        move |output: &str| {
+            print!("{}", output);
+            if let Some(last) = output.chars().last() {
+                at_line_start.set(last == '\n');
+            }
+        }
+    };
+    let mut cpu = CPU::with_io(Box::new(FrontendIo::new(read, write)));
+    let mut ram = RAM::new();
+    loader::load_object(&mut cpu, &mut ram, &object).map_err(|e| format!("Could not load the program: {}", e))?;
+    cpu.cycle_budget = options.max_steps;
+    if options.check_calls {
+        cpu.call_stack = Some(CallStack::new());
+    }
+    if options.profile {
+        cpu.profiler = Some(Profiler::new());
+    }
+    if let Some(path) = &options.trace {
+        let trace = TraceWriter::create(path).map_err(|e| format!("Could not create the trace `{}`: {}", path, e))?;
+        cpu.trace = Some(trace);
+    }
+
+    let result = cpu.run(&mut ram);
+    std::io::stdout().flush().ok();
+    if !at_line_start.get() {
+        eprintln!();
+    }
+    if let Some(trace) = cpu.trace.take() {
+        trace.finish().map_err(|e| format!("Could not write the trace: {}", e))?;
+    }
+
+    let status = match result {
+        Ok(RunOutcome::Halted) => EXIT_HALTED,
+        Ok(RunOutcome::BudgetExhausted { instruction_pointer }) => {
+            eprintln!("Stopped after {} steps, at address {:04}", cpu.cycles, instruction_pointer);
+            EXIT_LIMIT
+        }
+        Ok(outcome) => {
+            eprintln!("Execution stopped: {:?}", outcome);
+            EXIT_LIMIT
+        }
+        Err(fault) => {
+            eprintln!("Execution fault:");
+            eprintln!("{}", fault);
+            if let Some(line_number) = source_map.line_of(fault.get_site().address) {
+                match object.section_of(fault.get_site().address) {
+                    Some(section) => eprintln!("On line {} of {}", line_number, section.name),
+                    None if assembled => eprintln!("On line {} of {}", line_number, options.program),
+                    None => eprintln!("On line {} of its source", line_number),
+                }
+            }
+            if let Some(call_stack) = &cpu.call_stack {
+                eprint!("{}", call_stack.backtrace(fault.get_site().address, Some(&source_map)));
+            }
+            EXIT_FAULT
+        }
+    };
+
+    if options.dump_registers {
+        eprintln!("IP  {:04}", cpu.instruction_pointer);
+        eprintln!("CC  {}", cpu.condition_code.mnemonic());
+        for (register, value) in cpu.accumulators.iter().enumerate() {
+            eprintln!("R{}  {}", register, value);
+        }
+    }
+    for range in options.dump_memory.iter() {
+        for address in range.clone() {
+            eprintln!("{:04}: {:010}", address, ram[address]);
+        }
+    }
+    if let Some(profiler) = &cpu.profiler {
+        eprint!("{}", profiler.report(PROFILE_HOTTEST, Some(&source_map)));
+        if assembled {
+            let source = std::fs::read_to_string(&options.program).map_err(|e| format!("Could not read `{}`: {}", options.program, e))?;
+            eprintln!();
+            eprint!("{}", profiler.annotate(&source, &source_map));
+        }
+    }
+    Ok(status)
+}
+
+/// Reads the object file at `path`, or assembles the source file there into one.
+/// Also returns whether it was assembled, and so whether the source lines refer to `path`.
+fn read_program(path: &str) -> Result<(ObjectFile, bool), String> {
+    let bytes = std::fs::read(path).map_err(|e| format!("Could not read `{}`: {}", path, e))?;
+    if bytes.starts_with(BINARY_MAGIC) || bytes.starts_with(b"DRAMA object v") {
+        let object = ObjectFile::from_bytes(&bytes).map_err(|e| format!("Could not read `{}`: {}", path, e))?;
+        return Ok((object, false));
+    }
+
+    let output = Command::new(dasm_path()).args(["--object", path]).output()
+        .map_err(|e| format!("Could not start dasm: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("{}{}", String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr)));
+    }
+    let object = ObjectFile::from_bytes(&output.stdout).map_err(|e| format!("dasm printed an invalid object file: {}", e))?;
+    Ok((object, true))
+}
+
+fn dasm_path() -> PathBuf {
+    if let Some(path) = std::env::var_os("DASM") {
+        return PathBuf::from(path);
+    }
+    let sibling = std::env::current_exe().ok()
+        .map(|exe| exe.with_file_name(format!("dasm{}", std::env::consts::EXE_SUFFIX)));
+    match sibling {
+        Some(sibling) if sibling.exists() => sibling,
+        _ => PathBuf::from("dasm"),
+    }
+}
@@ -0,0 +1,366 @@
+//! Breakpoints and watchpoints, shared by the command line debugger and the GTK frontend.
+
+use std::fmt::Formatter;
+use std::str::FromStr;
+
+use drama_isa::Register;
+
+use crate::history::History;
+use crate::state::cpu::{RunOutcome, StepOutcome, CPU};
+use crate::state::cpu_fault::CpuFault;
+use crate::state::ram::RAM;
+
+/// Runs a program until it reaches a breakpoint or touches a watched location, in either direction.
+#[derive(Debug, Clone, Default)]
+pub struct Debugger {
+    pub breakpoints: Vec<Breakpoint>,
+    pub watchpoints: Vec<Watchpoint>,
+    /// Every instruction the debugger executes is recorded here, so it can be stepped back over.
+    pub history: History,
+    /// The instruction pointer and cycle count of the last stop at a breakpoint, so resuming from it
+    /// executes the instruction instead of stopping on it again.
+    breakpoint_stop: Option<(usize, u64)>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Debugger::default()
+    }
+
+    /// Stops before the instruction at `address` is executed.
+    pub fn break_at(&mut self, address: usize) {
+        self.breakpoints.push(Breakpoint { address, condition: None });
+    }
+
+    /// Stops before the instruction at `address` is executed, if `condition` holds at that point.
+    pub fn break_at_if(&mut self, address: usize, condition: BreakCondition) {
+        self.breakpoints.push(Breakpoint { address, condition: Some(condition) });
+    }
+
+    /// Stops after an instruction that accesses `target` in the way given by `kind`.
+    pub fn watch(&mut self, target: WatchTarget, kind: WatchKind) {
+        self.watchpoints.push(Watchpoint { target, kind });
+    }
+
+    /// Removes all breakpoints at `address`.
+    pub fn clear_breakpoints_at(&mut self, address: usize) {
+        self.breakpoints.retain(|breakpoint| breakpoint.address != address);
+    }
+
+    /// Executes instructions until the program stops, faults, hits a breakpoint or watchpoint,
+    /// or exhausts one of the [CPU's limits](CPU::cycle_budget).
+    ///
+    /// When the CPU is still where `resume` or [`reverse_continue`](Debugger::reverse_continue) last stopped at a
+    /// breakpoint, that instruction is executed first rather than stopped on again.
+    pub fn resume(&mut self, cpu: &mut CPU, ram: &mut RAM) -> Result<StopReason, CpuFault> {
+        let mut resuming = self.breakpoint_stop.take() == Some((cpu.instruction_pointer, cpu.cycles));
+        while !cpu.stopped {
+            if let Some(outcome) = cpu.check_limits() {
+                return Ok(StopReason::Limit(outcome));
+            }
+            if !resuming {
+                if let Some(breakpoint) = self.breakpoint_hit(cpu, ram) {
+                    let breakpoint = breakpoint.clone();
+                    self.breakpoint_stop = Some((cpu.instruction_pointer, cpu.cycles));
+                    return Ok(StopReason::Breakpoint(breakpoint));
+                }
+            }
+            resuming = false;
+
+            let outcome = self.step(cpu, ram)?;
+            if let Some(reason) = self.watchpoint_hit(&outcome) {
+                return Ok(reason);
+            }
+        }
+        Ok(StopReason::Halted)
+    }
+
+    /// Executes a single instruction, recording it in the [history](Debugger::history).
+    pub fn step(&mut self, cpu: &mut CPU, ram: &mut RAM) -> Result<StepOutcome, CpuFault> {
+        self.history.step(cpu, ram)
+    }
+
+    /// Undoes the most recent instruction. Returns `false` if the history holds nothing more to undo.
+    pub fn step_back(&mut self, cpu: &mut CPU, ram: &mut RAM) -> bool {
+        self.history.step_back(cpu, ram)
+    }
+
+    /// Steps back until the CPU is about to execute an instruction with a breakpoint on it,
+    /// or until the history runs out.
+    ///
+    /// The most recent instruction is always undone, so that reversing from a breakpoint doesn't stop on it again.
+    pub fn reverse_continue(&mut self, cpu: &mut CPU, ram: &mut RAM) -> StopReason {
+        while self.history.step_back(cpu, ram) {
+            if let Some(breakpoint) = self.breakpoint_hit(cpu, ram) {
+                let breakpoint = breakpoint.clone();
+                self.breakpoint_stop = Some((cpu.instruction_pointer, cpu.cycles));
+                return StopReason::Breakpoint(breakpoint);
+            }
+        }
+        StopReason::HistoryExhausted
+    }
+
+    /// The first breakpoint that applies to the instruction the CPU is about to execute.
+    pub fn breakpoint_hit(&self, cpu: &CPU, ram: &RAM) -> Option<&Breakpoint> {
+        self.breakpoints.iter()
+            .filter(|breakpoint| breakpoint.address == cpu.instruction_pointer)
+            .find(|breakpoint| breakpoint.condition.as_ref().is_none_or(|condition| condition.evaluate(cpu, ram)))
+    }
+
+    /// The first watchpoint the step triggered, for frontends that single-step with [`CPU::step`] themselves.
+    pub fn watchpoint_hit(&self, outcome: &StepOutcome) -> Option<StopReason> {
+        for watchpoint in self.watchpoints.iter() {
+            let (read, written) = match watchpoint.target {
+                WatchTarget::Memory(address) => (
+                    outcome.memory_reads.contains(&address),
+                    outcome.memory_changes.iter().any(|change| change.address == address),
+                ),
+                WatchTarget::Register(register) => (
+                    outcome.registers_read.contains(&register),
+                    outcome.registers_written.contains(&register),
+                ),
+            };
+            let access = match watchpoint.kind {
+                WatchKind::Read | WatchKind::ReadWrite if read => Access::Read,
+                WatchKind::Write | WatchKind::ReadWrite if written => Access::Write,
+                _ => continue,
+            };
+            return Some(StopReason::Watchpoint { watchpoint: *watchpoint, access, instruction_address: outcome.address });
+        }
+        None
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Breakpoint {
+    pub address: usize,
+    pub condition: Option<BreakCondition>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Watchpoint {
+    pub target: WatchTarget,
+    pub kind: WatchKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WatchTarget {
+    Memory(usize),
+    Register(Register),
+}
+
+/// Which accesses a watchpoint stops on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// Why [`Debugger::resume`] or [`Debugger::reverse_continue`] returned.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StopReason {
+    /// The program executed `STP`.
+    Halted,
+    /// The CPU is about to execute the instruction at the breakpoint.
+    Breakpoint(Breakpoint),
+    /// The instruction at `instruction_address` accessed a watched location and has been executed.
+    Watchpoint {
+        watchpoint: Watchpoint,
+        access: Access,
+        instruction_address: usize,
+    },
+    /// One of the CPU's limits was reached.
+    Limit(RunOutcome),
+    /// [`Debugger::reverse_continue`] undid every instruction the history remembered.
+    HistoryExhausted,
+}
+
+/// A comparison such as `R1 > 10` or `mem[200] == 0`, checked when a conditional breakpoint is reached.
+///
+/// Either side is a number, an accumulator (`R0` to `R9`), or a RAM cell `mem[...]`, whose address is itself
+/// any of these.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BreakCondition {
+    pub left: Operand,
+    pub comparison: Comparison,
+    pub right: Operand,
+}
+
+impl BreakCondition {
+    pub fn evaluate(&self, cpu: &CPU, ram: &RAM) -> bool {
+        let left = self.left.evaluate(cpu, ram);
+        let right = self.right.evaluate(cpu, ram);
+        match self.comparison {
+            Comparison::Equal => left == right,
+            Comparison::NotEqual => left != right,
+            Comparison::Less => left < right,
+            Comparison::LessOrEqual => left <= right,
+            Comparison::Greater => left > right,
+            Comparison::GreaterOrEqual => left >= right,
+        }
+    }
+}
+
+impl FromStr for BreakCondition {
+    type Err = ConditionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let start = s.find(|c| "=!<>".contains(c)).ok_or_else(|| ConditionError::MissingComparison(s.to_string()))?;
+        let (comparison, length) = match &s[start..] {
+            rest if rest.starts_with("==") => (Comparison::Equal, 2),
+            rest if rest.starts_with("!=") => (Comparison::NotEqual, 2),
+            rest if rest.starts_with("<=") => (Comparison::LessOrEqual, 2),
+            rest if rest.starts_with(">=") => (Comparison::GreaterOrEqual, 2),
+            rest if rest.starts_with('<') => (Comparison::Less, 1),
+            rest if rest.starts_with('>') => (Comparison::Greater, 1),
+            _ => return Err(ConditionError::MissingComparison(s.to_string())),
+        };
+        Ok(BreakCondition {
+            left: s[..start].parse()?,
+            comparison,
+            right: s[start + length..].parse()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Number(isize),
+    Register(Register),
+    Memory(Box<Operand>),
+}
+
+impl Operand {
+    pub fn evaluate(&self, cpu: &CPU, ram: &RAM) -> isize {
+        match self {
+            Operand::Number(number) => *number,
+            Operand::Register(register) => cpu.accumulators[register.index()],
+            Operand::Memory(address) => ram[address.evaluate(cpu, ram)],
+        }
+    }
+}
+
+impl FromStr for Operand {
+    type Err = ConditionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some(register) = Register::from_name(s) {
+            return Ok(Operand::Register(register));
+        }
+        if let Ok(number) = s.parse() {
+            return Ok(Operand::Number(number));
+        }
+        match s.strip_prefix("mem[").and_then(|rest| rest.strip_suffix(']')) {
+            Some(address) => Ok(Operand::Memory(Box::new(address.parse()?))),
+            None => Err(ConditionError::InvalidOperand(s.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConditionError {
+    MissingComparison(String),
+    InvalidOperand(String),
+}
+
+impl std::error::Error for ConditionError {}
+
+impl std::fmt::Display for ConditionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConditionError::MissingComparison(condition) => write!(f, "`{}` does not compare two values with ==, !=, <, <=, > or >=", condition),
+            ConditionError::InvalidOperand(operand) => write!(f, "`{}` is not a number, register or mem[...]", operand),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use drama_isa::word::{encode, encode_without_operand};
+    use drama_isa::{Condition, FunctionCode, Mode1, Mode2};
+
+    use super::*;
+    use crate::io::ScriptedIo;
+
+    /// Adds 1 to `R1` three times at 1, jumping back from 4, then stops at 5.
+    fn counting_loop() -> (CPU, RAM) {
+        let program = [
+            encode(FunctionCode::HIA, Mode1::Value, Mode2::NoIndex, Register::R2, Register::R0, 3),
+            encode(FunctionCode::OPT, Mode1::Value, Mode2::NoIndex, Register::R1, Register::R0, 1),
+            encode(FunctionCode::AFT, Mode1::Value, Mode2::NoIndex, Register::R2, Register::R0, 1),
+            encode(FunctionCode::VGL, Mode1::Value, Mode2::NoIndex, Register::R2, Register::R0, 0),
+            encode(FunctionCode::VSP, Mode1::Address, Mode2::NoIndex, Register::new(Condition::NNUL as usize).unwrap(), Register::R0, 1),
+            encode_without_operand(FunctionCode::STP),
+        ];
+        let mut ram = RAM::new();
+        ram.load_image(&program.iter().copied().enumerate().collect::<Vec<_>>()).unwrap();
+        (CPU::with_io(Box::new(ScriptedIo::default())), ram)
+    }
+
+    fn stopped_at(reason: Result<StopReason, CpuFault>) -> usize {
+        match reason {
+            Ok(StopReason::Breakpoint(breakpoint)) => breakpoint.address,
+            other => panic!("expected a breakpoint, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn a_breakpoint_at_the_entry_point_is_hit() {
+        let (mut cpu, mut ram) = counting_loop();
+        let mut debugger = Debugger::new();
+        debugger.break_at(0);
+        assert_eq!(stopped_at(debugger.resume(&mut cpu, &mut ram)), 0);
+        assert_eq!(cpu.cycles, 0);
+        assert_eq!(debugger.resume(&mut cpu, &mut ram), Ok(StopReason::Halted));
+        assert_eq!(cpu.accumulators[1], 3);
+    }
+
+    #[test]
+    fn a_breakpoint_in_a_loop_is_hit_every_time_around() {
+        let (mut cpu, mut ram) = counting_loop();
+        let mut debugger = Debugger::new();
+        debugger.break_at(1);
+        for count in 0..3 {
+            assert_eq!(stopped_at(debugger.resume(&mut cpu, &mut ram)), 1);
+            assert_eq!(cpu.accumulators[1], count);
+        }
+        assert_eq!(debugger.resume(&mut cpu, &mut ram), Ok(StopReason::Halted));
+        assert_eq!(cpu.accumulators[1], 3);
+    }
+
+    #[test]
+    fn single_stepping_from_a_breakpoint_leaves_it_armed() {
+        let (mut cpu, mut ram) = counting_loop();
+        let mut debugger = Debugger::new();
+        debugger.break_at(1);
+        assert_eq!(stopped_at(debugger.resume(&mut cpu, &mut ram)), 1);
+        for address in [2, 3, 4, 1] {
+            debugger.step(&mut cpu, &mut ram).unwrap();
+            assert_eq!(cpu.instruction_pointer, address);
+        }
+        assert_eq!(cpu.accumulators[1], 1);
+        // Stepping onto the breakpoint isn't stopping at it, so resuming stops right away
+        assert_eq!(stopped_at(debugger.resume(&mut cpu, &mut ram)), 1);
+        assert_eq!(cpu.accumulators[1], 1);
+        assert_eq!(stopped_at(debugger.resume(&mut cpu, &mut ram)), 1);
+        assert_eq!(cpu.accumulators[1], 2);
+    }
+}
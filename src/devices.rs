@@ -0,0 +1,249 @@
+//! Devices that can be mapped into RAM, so programs drive them with ordinary loads and stores.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use drama_isa::word;
+
+use crate::io::IoDevice;
+
+/// A device occupying a range of addresses, see [`RAM::map`](crate::state::ram::RAM::map).
+///
+/// Only the data accesses of instructions reach a device. Instruction fetches, indexing `RAM` directly (as debuggers
+/// and snapshots do) and stepping back in the history see the plain memory cells underneath.
+pub trait MemoryMappedDevice {
+    /// The number of addresses the device occupies.
+    fn size(&self) -> usize;
+    /// A load from the device. `offset` is relative to the start of its range.
+    fn load(&mut self, offset: usize) -> isize;
+    fn store(&mut self, offset: usize, value: isize);
+    /// Called once after every executed instruction.
+    fn tick(&mut self) {}
+    /// The interrupt line the device wants to raise, if any. Called once after every executed instruction.
+    fn take_interrupt(&mut self) -> Option<usize> {
+        None
+    }
+}
+
+/// A single write-only cell: storing a character code prints that character. Loads read 0.
+pub struct ConsolePort {
+    io: Box<dyn IoDevice>,
+}
+
+impl ConsolePort {
+    pub fn new(io: Box<dyn IoDevice>) -> Self {
+        ConsolePort { io }
+    }
+}
+
+impl MemoryMappedDevice for ConsolePort {
+    fn size(&self) -> usize {
+        1
+    }
+
+    fn load(&mut self, _offset: usize) -> isize {
+        0
+    }
+
+    fn store(&mut self, _offset: usize, value: isize) {
+        let character = word::decode_char(value).unwrap_or(std::char::REPLACEMENT_CHARACTER);
+        self.io.print_string(&character.to_string());
+    }
+}
+
+/// A single cell that counts the instructions executed since it was last written.
+#[derive(Default)]
+pub struct TimerRegister {
+    count: isize,
+}
+
+impl TimerRegister {
+    pub fn new() -> Self {
+        TimerRegister::default()
+    }
+}
+
+impl MemoryMappedDevice for TimerRegister {
+    fn size(&self) -> usize {
+        1
+    }
+
+    fn load(&mut self, _offset: usize) -> isize {
+        self.count
+    }
+
+    fn store(&mut self, _offset: usize, value: isize) {
+        self.count = value;
+    }
+
+    fn tick(&mut self) {
+        self.count = word::wrap(self.count as i128 + 1);
+    }
+}
+
+/// A single cell giving a new pseudo-random number between 0 and `WORD_MAX` on every load.
+/// The sequence only depends on the seed, so runs can be repeated. Storing a value reseeds it.
+pub struct RandomRegister {
+    random: Xorshift,
+}
+
+impl RandomRegister {
+    pub fn new(seed: u64) -> Self {
+        RandomRegister { random: Xorshift::new(seed) }
+    }
+}
+
+impl MemoryMappedDevice for RandomRegister {
+    fn size(&self) -> usize {
+        1
+    }
+
+    fn load(&mut self, _offset: usize) -> isize {
+        (self.random.next() % (word::WORD_MAX as u64 + 1)) as isize
+    }
+
+    fn store(&mut self, _offset: usize, value: isize) {
+        self.random = Xorshift::new(value as u64);
+    }
+}
+
+/// The pseudo-random numbers behind [`RandomRegister`] and [`RAM::fill_with_garbage`](crate::state::ram::RAM::fill_with_garbage).
+pub(crate) struct Xorshift {
+    state: u64,
+}
+
+impl Xorshift {
+    pub(crate) fn new(seed: u64) -> Self {
+        // xorshift never leaves 0
+        Xorshift { state: (seed ^ 0x9E37_79B9_7F4A_7C15).max(1) }
+    }
+
+    pub(crate) fn next(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+}
+
+/// A grid of character cells, stored row by row, that the frontend can display.
+///
+/// Clones share their cells, so a clone can be mapped into RAM while the original is used to look at the screen.
+#[derive(Clone)]
+pub struct TextScreen {
+    width: usize,
+    height: usize,
+    cells: Rc<RefCell<Vec<isize>>>,
+}
+
+impl TextScreen {
+    pub fn new(width: usize, height: usize) -> Self {
+        TextScreen {
+            width,
+            height,
+            cells: Rc::new(RefCell::new(vec![0; width * height])),
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// The screen as text, one line per row. Empty cells show as spaces.
+    pub fn contents(&self) -> String {
+        let cells = self.cells.borrow();
+        let mut contents = String::new();
+        for row in cells.chunks(self.width.max(1)) {
+            for &cell in row {
+                contents.push(match cell {
+                    0 => ' ',
+                    cell => word::decode_char(cell).unwrap_or(std::char::REPLACEMENT_CHARACTER),
+                });
+            }
+            contents.push('\n');
+        }
+        contents
+    }
+}
+
+impl MemoryMappedDevice for TextScreen {
+    fn size(&self) -> usize {
+        self.width * self.height
+    }
+
+    fn load(&mut self, offset: usize) -> isize {
+        self.cells.borrow()[offset]
+    }
+
+    fn store(&mut self, offset: usize, value: isize) {
+        self.cells.borrow_mut()[offset] = value;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use drama_isa::word::{encode_string, encode_without_operand};
+    use drama_isa::FunctionCode;
+
+    use super::*;
+    use crate::io::ScriptedIo;
+    use crate::state::cpu::CPU;
+    use crate::state::ram::RAM;
+
+    #[test]
+    fn the_console_port_prints_what_is_stored() {
+        let io = ScriptedIo::default();
+        let mut port = ConsolePort::new(Box::new(io.clone()));
+        port.store(0, 'h' as isize);
+        port.store(0, 'é' as isize);
+        port.store(0, -1);
+        assert_eq!(io.output(), "hé\u{FFFD}");
+        assert_eq!(port.load(0), 0);
+    }
+
+    #[test]
+    fn the_timer_counts_ticks_since_it_was_written() {
+        let mut timer = TimerRegister::new();
+        timer.tick();
+        timer.tick();
+        assert_eq!(timer.load(0), 2);
+        timer.store(0, word::WORD_MAX);
+        timer.tick();
+        assert_eq!(timer.load(0), word::WORD_MIN);
+    }
+
+    #[test]
+    fn the_random_register_repeats_for_the_same_seed() {
+        let mut random = RandomRegister::new(42);
+        let numbers: Vec<isize> = (0..100).map(|_| random.load(0)).collect();
+        assert!(numbers.iter().all(|number| (0..=word::WORD_MAX).contains(number)));
+        assert_ne!(numbers[0], numbers[1]);
+        assert_eq!(RandomRegister::new(42).load(0), numbers[0]);
+        random.store(0, 42);
+        assert_eq!((0..100).map(|_| random.load(0)).collect::<Vec<_>>(), numbers);
+    }
+
+    #[test]
+    fn drs_reads_a_string_from_a_device() {
+        let screen = TextScreen::new(4, 2);
+        let mut ram = RAM::new();
+        ram.map(100, Box::new(screen.clone())).unwrap();
+        for (offset, character) in encode_string("Hoi").into_iter().enumerate() {
+            ram.store(100 + offset, character);
+        }
+        assert_eq!(screen.contents(), "Hoi \n    \n");
+        assert_eq!(ram[100usize], 0);
+        assert_eq!(ram.read_string(100).as_deref(), Some("Hoi"));
+
+        ram.load_image(&[(0, encode_without_operand(FunctionCode::DRS))]).unwrap();
+        let io = ScriptedIo::default();
+        let mut cpu = CPU::with_io(Box::new(io.clone()));
+        cpu.accumulators[0] = 100;
+        cpu.step(&mut ram).unwrap();
+        assert_eq!(io.output(), "Hoi");
+    }
+}
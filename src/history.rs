@@ -0,0 +1,244 @@
+//! An undo log of executed instructions, so that a program can be stepped backwards.
+
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+
+use drama_isa::Register;
+
+use crate::call_stack::Frame;
+use crate::state::cpu::{ConditionCode, StepOutcome, CPU};
+use crate::state::cpu_fault::CpuFault;
+use crate::state::interrupts::Interrupts;
+use crate::state::ram::RAM;
+
+/// How many instructions a [`History`] remembers unless told otherwise.
+pub const DEFAULT_HISTORY_DEPTH: usize = 10_000;
+
+/// Remembers what the most recent instructions overwrote, so they can be undone.
+///
+/// Only the CPU and RAM are restored. Output that has been printed stays printed, and input that `LEZ` read is not
+/// given back to the input device, so executing a `LEZ` again reads the next number.
+#[derive(Debug, Clone)]
+pub struct History {
+    entries: VecDeque<UndoEntry>,
+    depth: usize,
+    /// The [`CPU::generation`] the entries were recorded in. They no longer apply once the CPU has been reset.
+    generation: u64,
+}
+
+/// The state an instruction overwrote. Accumulators and cells it left alone are not stored.
+#[derive(Debug, Clone)]
+struct UndoEntry {
+    instruction_pointer: usize,
+    instruction_register: isize,
+    cycles: u64,
+    condition_code: ConditionCode,
+    overflow: bool,
+    stopped: bool,
+    interrupts: Option<Interrupts>,
+    call: CallChange,
+    registers: Vec<(Register, isize)>,
+    registers_initialised: [bool; 10],
+    /// In the order they were written, so they are restored back to front, with whether they were initialised.
+    memory: Vec<(usize, isize, bool)>,
+    /// The address the instruction was fetched from, and whether it had been executed before.
+    fetched: (usize, bool),
+}
+
+/// What an instruction did to the [`CallStack`](crate::call_stack::CallStack).
+#[derive(Debug, Clone, Copy)]
+enum CallChange {
+    None,
+    Called,
+    Returned(Frame),
+}
+
+impl History {
+    /// Remembers up to `depth` instructions, forgetting the oldest ones first. A depth of 0 disables recording.
+    pub fn new(depth: usize) -> Self {
+        History {
+            entries: VecDeque::new(),
+            depth,
+            generation: 0,
+        }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Changes how many instructions are remembered, forgetting the oldest ones if there are too many already.
+    pub fn set_depth(&mut self, depth: usize) {
+        self.depth = depth;
+        while self.entries.len() > depth {
+            self.entries.pop_front();
+        }
+    }
+
+    /// The number of instructions that can currently be undone.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Executes a single instruction like [`CPU::step`], remembering how to undo it.
+    pub fn step(&mut self, cpu: &mut CPU, ram: &mut RAM) -> Result<StepOutcome, CpuFault> {
+        self.forget_if_reset(cpu);
+        let instruction_register = cpu.instruction_register;
+        let cycles = cpu.cycles;
+        let registers_initialised = cpu.registers_initialised;
+        let fetched = (cpu.instruction_pointer, ram.is_executed(cpu.instruction_pointer));
+        let overflow = cpu.overflow;
+        let stopped = cpu.stopped;
+        let interrupts = cpu.interrupts;
+        let call_depth = |cpu: &CPU| cpu.call_stack.as_ref().map_or(0, |call_stack| call_stack.frames().len());
+        let depth_before = call_depth(cpu);
+        let innermost_call = cpu.call_stack.as_ref().and_then(|call_stack| call_stack.frames().last().copied());
+        let outcome = cpu.step(ram)?;
+        let call = match (call_depth(cpu).cmp(&depth_before), innermost_call) {
+            (Ordering::Greater, _) => CallChange::Called,
+            (Ordering::Less, Some(frame)) => CallChange::Returned(frame),
+            _ => CallChange::None,
+        };
+
+        if self.depth > 0 {
+            if self.entries.len() >= self.depth {
+                self.entries.pop_front();
+            }
+            self.entries.push_back(UndoEntry {
+                instruction_pointer: outcome.address,
+                instruction_register,
+                cycles,
+                condition_code: outcome.condition_code_change.map_or(cpu.condition_code, |(old, _)| old),
+                overflow,
+                stopped,
+                interrupts,
+                call,
+                registers: outcome.register_changes.iter().map(|change| (change.register, change.old)).collect(),
+                registers_initialised,
+                memory: outcome.memory_changes.iter().map(|change| (change.address, change.old, change.was_initialised)).collect(),
+                fetched,
+            });
+        }
+        Ok(outcome)
+    }
+
+    /// Undoes the most recent instruction. Returns `false` if there is nothing left to undo.
+    /// Nothing is undone after the CPU has been [reset](CPU::reset), as by loading a program.
+    pub fn step_back(&mut self, cpu: &mut CPU, ram: &mut RAM) -> bool {
+        self.forget_if_reset(cpu);
+        let entry = match self.entries.pop_back() {
+            Some(entry) => entry,
+            None => return false,
+        };
+        for &(address, old, was_initialised) in entry.memory.iter().rev() {
+            ram[address] = old;
+            ram.set_initialised(address, was_initialised);
+        }
+        let (fetched, was_executed) = entry.fetched;
+        ram.set_executed(fetched, was_executed);
+        for &(register, old) in entry.registers.iter() {
+            cpu.accumulators[register.index()] = old;
+        }
+        cpu.registers_initialised = entry.registers_initialised;
+        cpu.instruction_pointer = entry.instruction_pointer;
+        cpu.instruction_register = entry.instruction_register;
+        cpu.condition_code = entry.condition_code;
+        cpu.overflow = entry.overflow;
+        cpu.stopped = entry.stopped;
+        cpu.interrupts = entry.interrupts;
+        if let Some(call_stack) = &mut cpu.call_stack {
+            match entry.call {
+                CallChange::Called => {
+                    call_stack.pop();
+                }
+                CallChange::Returned(frame) => call_stack.push(frame),
+                CallChange::None => {}
+            }
+        }
+        cpu.cycles = entry.cycles;
+        true
+    }
+
+    fn forget_if_reset(&mut self, cpu: &CPU) {
+        if self.generation != cpu.generation {
+            self.entries.clear();
+            self.generation = cpu.generation;
+        }
+    }
+}
+
+impl Default for History {
+    fn default() -> Self {
+        History::new(DEFAULT_HISTORY_DEPTH)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use drama_isa::word::{encode, encode_without_operand};
+    use drama_isa::{FunctionCode, Mode1, Mode2};
+
+    use super::*;
+    use crate::call_stack::CallStack;
+    use crate::io::ScriptedIo;
+    use crate::snapshot::Snapshot;
+
+    /// Everything [`History::step_back`] restores.
+    #[derive(Debug, PartialEq)]
+    struct State {
+        snapshot: Snapshot,
+        registers_initialised: [bool; 10],
+        cells: Vec<(bool, bool)>,
+        frames: Vec<Frame>,
+    }
+
+    fn state(cpu: &CPU, ram: &RAM) -> State {
+        State {
+            snapshot: Snapshot::capture(cpu, ram),
+            registers_initialised: cpu.registers_initialised,
+            cells: (0..10_000).map(|address| (ram.is_initialised(address), ram.is_executed(address))).collect(),
+            frames: cpu.call_stack.as_ref().unwrap().frames().to_vec(),
+        }
+    }
+
+    #[test]
+    fn stepping_back_restores_exactly_the_previous_state() {
+        let r1 = Register::new(1).unwrap();
+        let image = [
+            (0, encode(FunctionCode::HIA, Mode1::Value, Mode2::NoIndex, r1, Register::R0, -4)),
+            (1, encode(FunctionCode::SBR, Mode1::Address, Mode2::NoIndex, Register::R0, Register::R0, 10)),
+            (2, encode_without_operand(FunctionCode::STP)),
+            (10, encode(FunctionCode::BIG, Mode1::Address, Mode2::PostIncrement, r1, Register::new(2).unwrap(), 20)),
+            (11, encode(FunctionCode::OPT, Mode1::Value, Mode2::NoIndex, r1, Register::R0, 9)),
+            (12, encode_without_operand(FunctionCode::KTG)),
+        ];
+        let mut ram = RAM::new();
+        ram.load_image(&image).unwrap();
+        let mut cpu = CPU::with_io(Box::new(ScriptedIo::default()));
+        cpu.call_stack = Some(CallStack::new());
+        cpu.accumulators[9] = 100;
+        let mut history = History::default();
+
+        let mut states = vec![state(&cpu, &ram)];
+        while !cpu.stopped {
+            history.step(&mut cpu, &mut ram).unwrap();
+            states.push(state(&cpu, &ram));
+        }
+        assert_eq!(history.len(), 6);
+
+        states.pop();
+        while let Some(previous) = states.pop() {
+            assert!(history.step_back(&mut cpu, &mut ram));
+            assert_eq!(state(&cpu, &ram), previous);
+        }
+        assert!(!history.step_back(&mut cpu, &mut ram));
+    }
+}
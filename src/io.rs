@@ -0,0 +1,158 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{BufRead, Write};
+use std::rc::Rc;
+
+/// The console behind `LEZ`, `DRU`, `NWL` and `DRS`.
+pub trait IoDevice {
+    /// Reads an integer for `LEZ`. Returns `None` when no more input is available.
+    fn read_integer(&mut self) -> Option<isize>;
+    fn print_number(&mut self, number: isize);
+    fn print_newline(&mut self);
+    fn print_string(&mut self, string: &str);
+}
+
+/// Reads from stdin and prints to stdout.
+pub struct StdIo;
+
+impl IoDevice for StdIo {
+    fn read_integer(&mut self) -> Option<isize> {
+        std::io::stdout().flush().ok();
+        let stdin = std::io::stdin();
+        for line in stdin.lock().lines() {
+            let line = line.ok()?;
+            match line.trim().parse() {
+                Ok(number) => return Some(number),
+                Err(_) => eprintln!("`{}` is not an integer, try again", line.trim()),
+            }
+        }
+        None
+    }
+
+    fn print_number(&mut self, number: isize) {
+        print!("{}", number);
+    }
+
+    fn print_newline(&mut self) {
+        println!();
+    }
+
+    fn print_string(&mut self, string: &str) {
+        print!("{}", string);
+    }
+}
+
+/// Takes its input from a fixed script and collects all output in memory, for tests and grading.
+///
+/// Clones share their input and output, so a clone can be handed to the CPU while the original is used to inspect the output.
+#[derive(Clone, Default)]
+pub struct ScriptedIo {
+    input: Rc<RefCell<VecDeque<isize>>>,
+    output: Rc<RefCell<String>>,
+}
+
+impl ScriptedIo {
+    pub fn new<I: IntoIterator<Item = isize>>(input: I) -> Self {
+        ScriptedIo {
+            input: Rc::new(RefCell::new(input.into_iter().collect())),
+            output: Rc::default(),
+        }
+    }
+
+    /// Everything printed so far.
+    pub fn output(&self) -> String {
+        self.output.borrow().clone()
+    }
+
+    /// The input that hasn't been read yet.
+    pub fn remaining_input(&self) -> Vec<isize> {
+        self.input.borrow().iter().copied().collect()
+    }
+}
+
+impl IoDevice for ScriptedIo {
+    fn read_integer(&mut self) -> Option<isize> {
+        self.input.borrow_mut().pop_front()
+    }
+
+    fn print_number(&mut self, number: isize) {
+        self.output.borrow_mut().push_str(&number.to_string());
+    }
+
+    fn print_newline(&mut self) {
+        self.output.borrow_mut().push('\n');
+    }
+
+    fn print_string(&mut self, string: &str) {
+        self.output.borrow_mut().push_str(string);
+    }
+}
+
+/// Hands all I/O to a frontend through two callbacks.
+///
+/// This is what the GTK frontend plugs into: it can append output to a console view and ask for input with a dialog.
+pub struct FrontendIo<R, W> {
+    read: R,
+    write: W,
+}
+
+impl<R: FnMut() -> Option<isize>, W: FnMut(&str)> FrontendIo<R, W> {
+    pub fn new(read: R, write: W) -> Self {
+        FrontendIo { read, write }
+    }
+}
+
+impl<R: FnMut() -> Option<isize>, W: FnMut(&str)> IoDevice for FrontendIo<R, W> {
+    fn read_integer(&mut self) -> Option<isize> {
+        (self.read)()
+    }
+
+    fn print_number(&mut self, number: isize) {
+        (self.write)(&number.to_string());
+    }
+
+    fn print_newline(&mut self) {
+        (self.write)("\n");
+    }
+
+    fn print_string(&mut self, string: &str) {
+        (self.write)(string);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use drama_isa::word::encode_without_operand;
+    use drama_isa::FunctionCode;
+
+    use super::*;
+    use crate::state::cpu::CPU;
+    use crate::state::cpu_fault::CpuFault;
+    use crate::state::ram::RAM;
+
+    /// Echoes numbers on lines of their own until the input runs out.
+    fn echo(io: Box<dyn IoDevice>) -> CpuFault {
+        let program = [FunctionCode::LEZ, FunctionCode::DRU, FunctionCode::NWL, FunctionCode::LEZ, FunctionCode::DRU, FunctionCode::LEZ];
+        let mut ram = RAM::new();
+        ram.load_image(&program.iter().map(|&fc| encode_without_operand(fc)).enumerate().collect::<Vec<_>>()).unwrap();
+        let mut cpu = CPU::with_io(io);
+        let fault = cpu.run(&mut ram).unwrap_err();
+        assert_eq!(cpu.accumulators[0], -12);
+        fault
+    }
+
+    #[test]
+    fn programs_read_and_print_through_their_device() {
+        let io = ScriptedIo::new(vec![5, -12]);
+        assert!(matches!(echo(Box::new(io.clone())), CpuFault::NoInput(site) if site.address == 5));
+        assert_eq!(io.output(), "5\n-12");
+        assert!(io.remaining_input().is_empty());
+
+        let mut input = VecDeque::from(vec![5, -12]);
+        let output = Rc::new(RefCell::new(String::new()));
+        let written = output.clone();
+        let frontend = FrontendIo::new(move || input.pop_front(), move |text: &str| written.borrow_mut().push_str(text));
+        assert!(matches!(echo(Box::new(frontend)), CpuFault::NoInput(_)));
+        assert_eq!(*output.borrow(), "5\n-12");
+    }
+}
@@ -0,0 +1,20 @@
+//! The DRAMA simulator core, shared by the command line and the GTK frontend.
+
+pub mod call_stack;
+pub mod debugger;
+pub mod devices;
+pub mod history;
+pub mod io;
+pub mod loader;
+pub mod profiler;
+pub mod snapshot;
+pub mod trace;
+
+pub mod state {
+    pub mod cpu;
+    pub mod cpu_fault;
+    pub mod diagnostic;
+    pub mod interrupts;
+    pub mod ram;
+    pub mod semantics;
+}
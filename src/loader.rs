@@ -0,0 +1,145 @@
+//! Putting an assembled program into memory, as an image, an [object file](ObjectFile), or a
+//! [listing](drama_isa::listing) in dasm's output format.
+
+use std::fmt::Formatter;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use drama_isa::listing::{read_listing, ListingError};
+use drama_isa::object::{ObjectError, ObjectFile};
+
+use crate::state::cpu::CPU;
+use crate::state::ram::{ImageError, RAM};
+
+/// [Clears](RAM::clear) `ram`, writes `image` into it, [resets](CPU::reset) the CPU and points it at `entry_point`.
+/// Nothing changes if the image can't be loaded.
+pub fn load_program(cpu: &mut CPU, ram: &mut RAM, image: &[(usize, isize)], entry_point: usize) -> Result<(), LoadError> {
+    if entry_point >= 10_000 {
+        return Err(LoadError::EntryPointOutOfRange(entry_point));
+    }
+    // Check the image on its own before anything is cleared
+    RAM::new().load_image(image).map_err(LoadError::Image)?;
+    ram.clear();
+    ram.load_image(image).map_err(LoadError::Image)?;
+    cpu.reset();
+    cpu.instruction_pointer = entry_point;
+    Ok(())
+}
+
+/// Reads a listing file and [loads](load_program) it.
+pub fn load_listing_file<P: AsRef<Path>>(cpu: &mut CPU, ram: &mut RAM, path: P, entry_point: usize) -> Result<(), LoadError> {
+    let listing = fs::read_to_string(path).map_err(LoadError::Io)?;
+    load_program(cpu, ram, &read_listing(&listing).map_err(LoadError::Listing)?, entry_point)
+}
+
+/// [Loads](load_program) the words of an object file and starts at its entry point.
+/// An object that imports labels has to be linked first.
+pub fn load_object(cpu: &mut CPU, ram: &mut RAM, object: &ObjectFile) -> Result<(), LoadError> {
+    if !object.imports.is_empty() {
+        return Err(LoadError::Unlinked(object.imports.clone()));
+    }
+    load_program(cpu, ram, &object.image(), object.entry_point)
+}
+
+/// Reads an object file in either form and [loads](load_object) it. The object is returned for its symbols and source lines.
+pub fn load_object_file<P: AsRef<Path>>(cpu: &mut CPU, ram: &mut RAM, path: P) -> Result<ObjectFile, LoadError> {
+    let object = ObjectFile::from_bytes(&fs::read(path).map_err(LoadError::Io)?).map_err(LoadError::Object)?;
+    load_object(cpu, ram, &object)?;
+    Ok(object)
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    Listing(ListingError),
+    Image(ImageError),
+    EntryPointOutOfRange(usize),
+    Object(ObjectError),
+    /// The object file still imports these labels.
+    Unlinked(Vec<String>),
+}
+
+impl LoadError {
+    pub fn get_line(&self) -> Option<usize> {
+        match self {
+            LoadError::Listing(error) => error.get_line(),
+            LoadError::Object(error) => error.get_line(),
+            _ => None,
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl std::fmt::Display for LoadError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoadError::Io(error) => write!(f, "Could not read the program: {}", error),
+            LoadError::Listing(error) => write!(f, "{}", error),
+            LoadError::Image(error) => write!(f, "{}", error),
+            LoadError::EntryPointOutOfRange(address) => write!(f, "The entry point {} is outside of memory", address),
+            LoadError::Object(error) => write!(f, "{}", error),
+            LoadError::Unlinked(imports) => write!(f, "The program imports {} and has to be linked first, with `dasm --link`", imports.join(", ")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A machine that has run for a while, to tell whether a failed load left it alone.
+    fn used_machine() -> (CPU, RAM) {
+        let (mut cpu, mut ram) = (CPU::new(), RAM::new());
+        load_program(&mut cpu, &mut ram, &[(0, 1112000005), (1, 9900000000)], 0).unwrap();
+        cpu.instruction_pointer = 1;
+        cpu.accumulators[1] = 5;
+        (cpu, ram)
+    }
+
+    fn assert_untouched(cpu: &CPU, ram: &RAM) {
+        assert_eq!(ram[0usize], 1112000005);
+        assert_eq!(ram[1usize], 9900000000);
+        assert_eq!(cpu.instruction_pointer, 1);
+        assert_eq!(cpu.accumulators[1], 5);
+    }
+
+    #[test]
+    fn a_program_is_loaded_over_the_previous_one() {
+        let (mut cpu, mut ram) = used_machine();
+        load_program(&mut cpu, &mut ram, &[(5, 42)], 5).unwrap();
+        assert_eq!(ram[0usize], 0);
+        assert_eq!(ram[5usize], 42);
+        assert!(ram.is_loaded(5) && !ram.is_loaded(0));
+        assert_eq!(cpu.instruction_pointer, 5);
+        assert_eq!(cpu.accumulators[1], 0);
+    }
+
+    #[test]
+    fn overlapping_words_are_not_loaded() {
+        let (mut cpu, mut ram) = used_machine();
+        let result = load_program(&mut cpu, &mut ram, &[(7, 1), (8, 2), (7, 3)], 0);
+        assert!(matches!(result, Err(LoadError::Image(ImageError::Overlap(7)))));
+        assert_untouched(&cpu, &ram);
+
+        let listed = read_listing("0007: 1\n2\n0007: 3").map_err(LoadError::Listing);
+        assert!(matches!(listed, Err(LoadError::Listing(ListingError::Overlap { line: 3, address: 7, first_line: 1 }))));
+        assert_eq!(listed.unwrap_err().get_line(), Some(3));
+    }
+
+    #[test]
+    fn addresses_outside_of_memory_are_not_loaded() {
+        let (mut cpu, mut ram) = used_machine();
+        let result = load_program(&mut cpu, &mut ram, &[(9999, 1), (10_000, 2)], 0);
+        assert!(matches!(result, Err(LoadError::Image(ImageError::AddressOutOfRange(10_000)))));
+        assert_untouched(&cpu, &ram);
+
+        let result = load_program(&mut cpu, &mut ram, &[(9999, 1)], 10_000);
+        assert!(matches!(result, Err(LoadError::EntryPointOutOfRange(10_000))));
+        assert_untouched(&cpu, &ram);
+
+        let listed = read_listing("9999: 1\n2").map_err(LoadError::Listing);
+        assert!(matches!(listed, Err(LoadError::Listing(ListingError::AddressOutOfRange { line: 2, address: 10_000 }))));
+    }
+}
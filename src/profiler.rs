@@ -0,0 +1,245 @@
+//! Counts where a program spends its instructions, per address and per subroutine.
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+use drama_isa::source_map::SourceMap;
+
+use crate::call_stack::CallStack;
+use crate::state::cpu::StepOutcome;
+
+/// Collects execution counts, as set in [`CPU::profiler`](crate::state::cpu::CPU::profiler).
+///
+/// A subroutine is identified by the address `SBR` jumps to, and lasts until the matching `KTG`.
+/// Interrupt handlers count as subroutines too, ending at their `KTO`. Calls are followed with a [`CallStack`]
+/// of the profiler's own, so profiling works whether or not the CPU checks calls.
+#[derive(Debug, Clone)]
+pub struct Profiler {
+    counts: Vec<u64>,
+    subroutines: BTreeMap<usize, SubroutineCost>,
+    call_stack: CallStack,
+    /// [`Profiler::total`] when each pending call of the call stack was made, outermost first.
+    started_at: Vec<u64>,
+    total: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SubroutineCost {
+    pub calls: u64,
+    /// Instructions executed while the subroutine or anything it called was running, its `KTG` included.
+    /// Recursive calls are only counted once.
+    pub inclusive: u64,
+    /// Instructions of the subroutine itself.
+    pub exclusive: u64,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler {
+            counts: vec![0; 10_000],
+            subroutines: BTreeMap::new(),
+            call_stack: CallStack::new(),
+            started_at: Vec::new(),
+            total: 0,
+        }
+    }
+
+    pub fn record(&mut self, outcome: &StepOutcome) {
+        self.total += 1;
+        self.counts[outcome.address] += 1;
+        let innermost = self.call_stack.frames().last().copied();
+        if let Some(frame) = innermost {
+            self.subroutines.entry(frame.entry).or_default().exclusive += 1;
+        }
+
+        let depth = self.call_stack.frames().len();
+        self.call_stack.record(outcome);
+        match self.call_stack.frames().len().cmp(&depth) {
+            Ordering::Greater => {
+                let entry = self.call_stack.frames()[depth].entry;
+                self.subroutines.entry(entry).or_default().calls += 1;
+                self.started_at.push(self.total);
+            }
+            Ordering::Less => {
+                if let (Some(frame), Some(started_at)) = (innermost, self.started_at.pop()) {
+                    self.close(frame.entry, started_at);
+                }
+            }
+            Ordering::Equal => {}
+        }
+    }
+
+    /// Adds up a call of `entry` that returned, once the call stack no longer holds it.
+    fn close(&mut self, entry: usize, started_at: u64) {
+        // An outer call of the same subroutine already covers this one
+        if !self.call_stack.frames().iter().any(|outer| outer.entry == entry) {
+            self.subroutines.entry(entry).or_default().inclusive += self.total - started_at;
+        }
+    }
+
+    /// The number of instructions recorded.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// How often the instruction at `address` was executed.
+    pub fn count(&self, address: usize) -> u64 {
+        self.counts[address]
+    }
+
+    /// The cost of every subroutine that was called, by entry address.
+    /// Subroutines that are still running are counted up to now.
+    pub fn subroutines(&self) -> BTreeMap<usize, SubroutineCost> {
+        let mut profiler = self.clone();
+        while let (Some(frame), Some(started_at)) = (profiler.call_stack.pop(), profiler.started_at.pop()) {
+            profiler.close(frame.entry, started_at);
+        }
+        profiler.subroutines
+    }
+
+    /// The `limit` most executed addresses with their counts, most executed first.
+    pub fn hottest(&self, limit: usize) -> Vec<(usize, u64)> {
+        let mut hottest: Vec<(usize, u64)> = self.counts.iter().copied().enumerate().filter(|&(_, count)| count > 0).collect();
+        hottest.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        hottest.truncate(limit);
+        hottest
+    }
+
+    /// A table of the `limit` hottest addresses and of all subroutines, with source lines if a map is given.
+    pub fn report(&self, limit: usize, source_map: Option<&SourceMap>) -> String {
+        let line = |address: usize| source_map
+            .and_then(|source_map| source_map.line_of(address))
+            .map_or_else(|| "-".to_string(), |line_number| line_number.to_string());
+        let name = |address: usize| source_map.map_or_else(|| "-".to_string(), |source_map| source_map.symbolize(address));
+        let share = |count: u64| 100.0 * count as f64 / self.total.max(1) as f64;
+
+        let mut report = format!("{} instructions executed\n\nHottest addresses:\n", self.total);
+        report.push_str(&format!("{:>7} {:>10} {:>7} {:>6}\n", "address", "count", "share", "line"));
+        for (address, count) in self.hottest(limit) {
+            report.push_str(&format!("{:>7} {:>10} {:>6.1}% {:>6}\n", format!("{:04}", address), count, share(count), line(address)));
+        }
+
+        let mut subroutines: Vec<(usize, SubroutineCost)> = self.subroutines().into_iter().collect();
+        if !subroutines.is_empty() {
+            subroutines.sort_by(|a, b| b.1.inclusive.cmp(&a.1.inclusive).then(a.0.cmp(&b.0)));
+            report.push_str("\nSubroutines:\n");
+            report.push_str(&format!("{:>7} {:>6} {:>8} {:>10} {:>7} {:>10} {:>7}  {}\n", "entry", "line", "calls", "inclusive", "share", "exclusive", "share", "name"));
+            for (entry, cost) in subroutines {
+                report.push_str(&format!(
+                    "{:>7} {:>6} {:>8} {:>10} {:>6.1}% {:>10} {:>6.1}%  {}\n",
+                    format!("{:04}", entry), line(entry), cost.calls,
+                    cost.inclusive, share(cost.inclusive), cost.exclusive, share(cost.exclusive), name(entry)
+                ));
+            }
+        }
+        report
+    }
+
+    /// The assembly source with the number of instructions executed on every line in front of it.
+    pub fn annotate(&self, source: &str, source_map: &SourceMap) -> String {
+        let mut per_line: BTreeMap<usize, u64> = BTreeMap::new();
+        for (address, line_number) in source_map.iter() {
+            *per_line.entry(line_number).or_default() += self.counts[address];
+        }
+
+        let mut annotated = String::new();
+        for (index, text) in source.lines().enumerate() {
+            match per_line.get(&(index + 1)) {
+                Some(count) => annotated.push_str(&format!("{:>10} | {}\n", count, text)),
+                None => annotated.push_str(&format!("{:>10} | {}\n", "", text)),
+            }
+        }
+        annotated
+    }
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Profiler::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use drama_isa::word::{encode, encode_without_operand};
+    use drama_isa::{FunctionCode, Mode1, Mode2, Register};
+
+    use super::*;
+    use crate::io::ScriptedIo;
+    use crate::state::cpu::CPU;
+    use crate::state::ram::RAM;
+
+    fn call(entry: isize) -> isize {
+        encode(FunctionCode::SBR, Mode1::Address, Mode2::NoIndex, Register::R0, Register::R0, entry)
+    }
+
+    fn increment() -> isize {
+        encode(FunctionCode::OPT, Mode1::Value, Mode2::NoIndex, Register::new(1).unwrap(), Register::R0, 1)
+    }
+
+    fn profile(image: &[(usize, isize)], cycle_budget: Option<u64>) -> Profiler {
+        let mut ram = RAM::new();
+        ram.load_image(image).unwrap();
+        let mut cpu = CPU::with_io(Box::new(ScriptedIo::default()));
+        cpu.profiler = Some(Profiler::new());
+        cpu.cycle_budget = cycle_budget;
+        cpu.run(&mut ram).unwrap();
+        cpu.profiler.unwrap()
+    }
+
+    /// Calls the subroutine at 4 twice; it adds 1 to `R1` and returns.
+    fn profile_calls() -> Profiler {
+        let image = [
+            (0, call(4)),
+            (1, call(4)),
+            (2, encode_without_operand(FunctionCode::STP)),
+            (4, increment()),
+            (5, encode_without_operand(FunctionCode::KTG)),
+        ];
+        profile(&image, None)
+    }
+
+    #[test]
+    fn every_executed_instruction_is_counted() {
+        let profiler = profile_calls();
+        assert_eq!(profiler.total(), 7);
+        assert_eq!([0, 1, 2, 3, 4, 5].map(|address| profiler.count(address)), [1, 1, 1, 0, 2, 2]);
+        assert_eq!(profiler.hottest(2), vec![(4, 2), (5, 2)]);
+    }
+
+    #[test]
+    fn subroutines_are_counted_per_call() {
+        let profiler = profile_calls();
+        let subroutines = profiler.subroutines();
+        assert_eq!(subroutines.len(), 1);
+        assert_eq!(subroutines[&4], SubroutineCost { calls: 2, inclusive: 4, exclusive: 4 });
+
+        let mut source_map = SourceMap::new();
+        source_map.insert_label("increment", 4);
+        source_map.insert(4, 7);
+        let report = profiler.report(1, Some(&source_map));
+        assert!(report.starts_with("7 instructions executed\n"));
+        assert!(report.lines().any(|line| line.contains("0004") && line.contains(" 2 ") && line.ends_with("increment")));
+    }
+
+    #[test]
+    fn nested_calls_count_towards_their_callers() {
+        // The subroutine at 10 calls the one at 20
+        let image = [
+            (0, call(10)),
+            (1, encode_without_operand(FunctionCode::STP)),
+            (10, call(20)),
+            (11, encode_without_operand(FunctionCode::KTG)),
+            (20, increment()),
+            (21, encode_without_operand(FunctionCode::KTG)),
+        ];
+        let subroutines = profile(&image, None).subroutines();
+        assert_eq!(subroutines[&10], SubroutineCost { calls: 1, inclusive: 4, exclusive: 2 });
+        assert_eq!(subroutines[&20], SubroutineCost { calls: 1, inclusive: 2, exclusive: 2 });
+
+        // Stopped at 21, before either returned
+        let subroutines = profile(&image, Some(3)).subroutines();
+        assert_eq!(subroutines[&10], SubroutineCost { calls: 1, inclusive: 2, exclusive: 1 });
+        assert_eq!(subroutines[&20], SubroutineCost { calls: 1, inclusive: 1, exclusive: 1 });
+    }
+}
@@ -0,0 +1,265 @@
+//! Saving the complete machine to a text file and loading it back.
+//!
+//! A snapshot looks like this:
+//!
+//! ```text
+//! DRAMA snapshot v1
+//! ip 12
+//! ir 1112000005
+//! cc POS
+//! accumulators 5 0 0 0 0 0 0 0 0 0
+//! stopped false
+//! overflow false
+//! cycles 11
+//! memory
+//! 0000: 1112000005
+//! 0200: 5
+//! ```
+//!
+//! Cells that are not listed hold 0. Blank lines and comments after `|` are ignored, so a snapshot can be
+//! annotated by hand before it is handed out.
+
+use std::fmt::Formatter;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+use drama_isa::word;
+
+use crate::state::cpu::{ConditionCode, CPU};
+use crate::state::ram::RAM;
+
+/// The format version written by this simulator. Older versions are read as long as they are supported.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+const HEADER: &str = "DRAMA snapshot v";
+const MEMORY_SIZE: usize = 10_000;
+
+/// The state of the CPU and every RAM cell at one moment.
+///
+/// The CPU's I/O device, trace and limits are settings rather than state, so they are neither saved nor restored.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub instruction_pointer: usize,
+    pub instruction_register: isize,
+    pub condition_code: ConditionCode,
+    pub accumulators: [isize; 10],
+    pub stopped: bool,
+    pub overflow: bool,
+    pub cycles: u64,
+    pub memory: Vec<isize>,
+}
+
+impl Snapshot {
+    pub fn capture(cpu: &CPU, ram: &RAM) -> Self {
+        Snapshot {
+            instruction_pointer: cpu.instruction_pointer,
+            instruction_register: cpu.instruction_register,
+            condition_code: cpu.condition_code,
+            accumulators: cpu.accumulators,
+            stopped: cpu.stopped,
+            overflow: cpu.overflow,
+            cycles: cpu.cycles,
+            memory: (0..MEMORY_SIZE).map(|address| ram[address]).collect(),
+        }
+    }
+
+    /// Puts the machine back in the saved state. Every cell and register counts as
+    /// [initialised](RAM::is_initialised) afterwards, as the snapshot holds a value for each.
+    pub fn restore(&self, cpu: &mut CPU, ram: &mut RAM) {
+        cpu.instruction_pointer = self.instruction_pointer;
+        cpu.instruction_register = self.instruction_register;
+        cpu.condition_code = self.condition_code;
+        cpu.accumulators = self.accumulators;
+        cpu.registers_initialised = [true; 10];
+        cpu.stopped = self.stopped;
+        cpu.overflow = self.overflow;
+        cpu.cycles = self.cycles;
+        for (address, &value) in self.memory.iter().enumerate() {
+            ram[address] = value;
+            ram.mark_initialised(address);
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, SnapshotError> {
+        fs::read_to_string(path).map_err(SnapshotError::Io)?.parse()
+    }
+}
+
+impl std::fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}{}", HEADER, SNAPSHOT_VERSION)?;
+        writeln!(f, "ip {}", self.instruction_pointer)?;
+        writeln!(f, "ir {}", self.instruction_register)?;
+        writeln!(f, "cc {}", self.condition_code.mnemonic())?;
+        let accumulators: Vec<String> = self.accumulators.iter().map(|value| value.to_string()).collect();
+        writeln!(f, "accumulators {}", accumulators.join(" "))?;
+        writeln!(f, "stopped {}", self.stopped)?;
+        writeln!(f, "overflow {}", self.overflow)?;
+        writeln!(f, "cycles {}", self.cycles)?;
+        writeln!(f, "memory")?;
+        for (address, &value) in self.memory.iter().enumerate() {
+            if value != 0 {
+                writeln!(f, "{:04}: {}", address, value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Snapshot {
+    type Err = SnapshotError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lines = s.lines().enumerate()
+            .map(|(index, line)| (index + 1, line.split('|').next().unwrap().trim()))
+            .filter(|(_, line)| !line.is_empty());
+
+        let version = match lines.next() {
+            Some((_, line)) if line.starts_with(HEADER) => &line[HEADER.len()..],
+            _ => return Err(SnapshotError::MissingHeader),
+        };
+        if version.parse() != Ok(SNAPSHOT_VERSION) {
+            return Err(SnapshotError::UnsupportedVersion(version.to_string()));
+        }
+
+        let mut snapshot = Snapshot {
+            instruction_pointer: 0,
+            instruction_register: 0,
+            condition_code: ConditionCode::Eql,
+            accumulators: [0; 10],
+            stopped: false,
+            overflow: false,
+            cycles: 0,
+            memory: vec![0; MEMORY_SIZE],
+        };
+        let mut in_memory = false;
+        for (line_number, line) in lines {
+            let malformed = || SnapshotError::Malformed { line: line_number, text: line.to_string() };
+            let word = |value: &str| -> Result<isize, SnapshotError> {
+                let value = value.parse().map_err(|_| malformed())?;
+                if !word::fits(value as i128) {
+                    return Err(SnapshotError::ValueOutOfRange { line: line_number, value });
+                }
+                Ok(value)
+            };
+            if in_memory {
+                let mut parts = line.splitn(2, ':');
+                let address: usize = parts.next().unwrap().trim().parse().map_err(|_| malformed())?;
+                let value = word(parts.next().ok_or_else(malformed)?.trim())?;
+                if address >= MEMORY_SIZE {
+                    return Err(SnapshotError::AddressOutOfRange { line: line_number, address });
+                }
+                snapshot.memory[address] = value;
+                continue;
+            }
+
+            let mut parts = line.splitn(2, ' ');
+            let key = parts.next().unwrap();
+            let value = parts.next().unwrap_or("").trim();
+            match key {
+                "ip" => snapshot.instruction_pointer = value.parse().ok().filter(|&ip| ip < MEMORY_SIZE).ok_or_else(malformed)?,
+                "ir" => snapshot.instruction_register = word(value)?,
+                "cc" => snapshot.condition_code = ConditionCode::from_mnemonic(value).ok_or_else(malformed)?,
+                "accumulators" => {
+                    let values = value.split_whitespace().map(word).collect::<Result<Vec<isize>, _>>()?;
+                    if values.len() != 10 {
+                        return Err(malformed());
+                    }
+                    snapshot.accumulators.copy_from_slice(&values);
+                }
+                "stopped" => snapshot.stopped = value.parse().map_err(|_| malformed())?,
+                "overflow" => snapshot.overflow = value.parse().map_err(|_| malformed())?,
+                "cycles" => snapshot.cycles = value.parse().map_err(|_| malformed())?,
+                "memory" if value.is_empty() => in_memory = true,
+                _ => return Err(malformed()),
+            }
+        }
+        Ok(snapshot)
+    }
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    MissingHeader,
+    UnsupportedVersion(String),
+    Malformed { line: usize, text: String },
+    AddressOutOfRange { line: usize, address: usize },
+    /// A register or cell holds a number that doesn't fit in a word.
+    ValueOutOfRange { line: usize, value: isize },
+}
+
+impl SnapshotError {
+    pub fn get_line(&self) -> Option<usize> {
+        match self {
+            SnapshotError::Malformed { line, .. } => Some(*line),
+            SnapshotError::AddressOutOfRange { line, .. } => Some(*line),
+            SnapshotError::ValueOutOfRange { line, .. } => Some(*line),
+            _ => None,
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl std::fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SnapshotError::Io(error) => write!(f, "Could not read the snapshot: {}", error),
+            SnapshotError::MissingHeader => write!(f, "Not a snapshot: the first line should be `{}{}`", HEADER, SNAPSHOT_VERSION),
+            SnapshotError::UnsupportedVersion(version) => write!(f, "Snapshot version `{}` is not supported, only version {} is", version, SNAPSHOT_VERSION),
+            SnapshotError::Malformed { line, text } => write!(f, "Line {}: `{}` is not understood", line, text),
+            SnapshotError::AddressOutOfRange { line, address } => write!(f, "Line {}: address {} is outside of memory", line, address),
+            SnapshotError::ValueOutOfRange { line, value } => write!(f, "Line {}: {} does not fit in a word", line, value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_saved_snapshot_restores_the_same_machine() {
+        let mut cpu = CPU::new();
+        let mut ram = RAM::new();
+        cpu.instruction_pointer = 12;
+        cpu.instruction_register = 1_112_000_005;
+        cpu.condition_code = ConditionCode::Neg;
+        cpu.accumulators = [5, -1, 0, 0, word::WORD_MAX, word::WORD_MIN, 0, 0, 0, 9999];
+        cpu.overflow = true;
+        cpu.cycles = 11;
+        ram[0usize] = 1_112_000_005;
+        ram[200usize] = word::WORD_MIN;
+        ram[9999usize] = -3;
+        let snapshot = Snapshot::capture(&cpu, &ram);
+
+        let loaded: Snapshot = snapshot.to_string().parse().unwrap();
+        assert_eq!(loaded, snapshot);
+        let (mut restored_cpu, mut restored_ram) = (CPU::new(), RAM::new());
+        loaded.restore(&mut restored_cpu, &mut restored_ram);
+        assert_eq!(Snapshot::capture(&restored_cpu, &restored_ram), snapshot);
+    }
+
+    #[test]
+    fn values_that_do_not_fit_in_a_word_are_rejected() {
+        let parse = |lines: &str| format!("{}{}\n{}", HEADER, SNAPSHOT_VERSION, lines).parse::<Snapshot>();
+        assert!(parse("ir 4999999999\nmemory\n0001: -5000000000").is_ok());
+        for (lines, line, value) in [
+            ("ir 5000000000", 2, 5_000_000_000),
+            ("accumulators 0 0 0 -5000000001 0 0 0 0 0 0", 2, -5_000_000_001),
+            ("memory\n0001: 12345678901", 3, 12_345_678_901),
+        ] {
+            match parse(lines) {
+                Err(SnapshotError::ValueOutOfRange { line: found_line, value: found_value }) => assert_eq!((found_line, found_value), (line, value)),
+                other => panic!("`{}` gave {:?}", lines, other),
+            }
+        }
+    }
+}
@@ -1,166 +1,579 @@
-use crate::state::ram::{self, RAM};
+use std::collections::HashSet;
+use std::time::Instant;
 
-macro_rules! num_range {
-    // `()` indicates that the macro takes no argument.
-    ($c:expr, $s:expr; $e:expr) => {
-        {
-            const DIV: usize = 10usize.pow(10 - $e);
-            const MOD: usize = 10usize.pow($e - $s);
-            ($c / DIV) % MOD
-        }
-    };
-}
+use drama_isa::{word, Condition, DecodedInstruction, FunctionCode, Mode1, Mode2, Register};
+
+use crate::call_stack::CallStack;
+use crate::io::{IoDevice, StdIo};
+use crate::profiler::Profiler;
+use crate::state::cpu_fault::{CpuFault, FaultSite};
+use crate::state::diagnostic::Diagnostic;
+use crate::state::interrupts::{Interrupts, TIMER_LINE};
+use crate::state::ram::{self, RAM};
+use crate::state::semantics::{AddressRange, ConditionCodeSource, SemanticsProfile};
+use crate::trace::TraceWriter;
 
 pub struct CPU {
     pub instruction_pointer: usize,
     pub instruction_register: isize,
     pub condition_code: ConditionCode,
     pub accumulators: [isize; 10],
+    /// Which accumulators an instruction has written.
+    pub registers_initialised: [bool; 10],
     pub stopped: bool,
+    /// Set whenever a result didn't fit in a word. It stays set until cleared.
+    pub overflow: bool,
+    pub overflow_mode: OverflowMode,
+    /// How to behave where simulators disagree.
+    pub semantics: SemanticsProfile,
+    /// The number of instructions executed so far.
+    pub cycles: u64,
+    /// [`run`](CPU::run) stops once [`cycles`](CPU::cycles) reaches this many instructions.
+    pub cycle_budget: Option<u64>,
+    /// [`run`](CPU::run) stops once this moment has passed.
+    pub deadline: Option<Instant>,
+    /// Whether to fetch through the [decoded instruction cache](RAM::decode) instead of decoding every word again.
+    /// Either way the results are the same.
+    pub decode_cache: bool,
+    /// Whether [`run`](CPU::run) watches for the machine returning to a state it has been in before.
+    pub detect_loops: bool,
+    pub io: Box<dyn IoDevice>,
+    /// Records every instruction that executes without a fault.
+    pub trace: Option<TraceWriter>,
+    /// Counts every instruction that executes without a fault.
+    pub profiler: Option<Profiler>,
+    /// Tracks subroutine calls, and makes the CPU check the stack against them.
+    pub call_stack: Option<CallStack>,
+    /// What happens to stores into [write-protected](RAM::write_protect) cells.
+    pub write_protection: WriteProtection,
+    /// Whether to add a diagnostic for every store into a cell that has been executed before.
+    pub report_self_modifying_code: bool,
+    /// Whether to add a diagnostic for every read of a register or [cell](RAM::is_initialised) nothing has written yet.
+    pub report_uninitialised_reads: bool,
+    /// Warnings about the program so far, oldest first.
+    pub diagnostics: Vec<Diagnostic>,
+    /// The interrupt extension, with `KTO`, `OBA` and `OBU`. Without it those instructions are invalid opcodes.
+    pub interrupts: Option<Interrupts>,
+    /// Counts the [resets](CPU::reset), so a [`History`](crate::history::History) can tell its entries no longer apply.
+    pub(crate) generation: u64,
 }
 
 impl CPU {
     pub fn new() -> Self {
+        CPU::with_io(Box::new(StdIo))
+    }
+
+    pub fn with_io(io: Box<dyn IoDevice>) -> Self {
         CPU {
             instruction_pointer: 0,
             instruction_register: 0,
             condition_code: ConditionCode::Eql,
             accumulators: [0; 10],
+            registers_initialised: [false; 10],
             stopped: false,
+            overflow: false,
+            overflow_mode: OverflowMode::Wrap,
+            semantics: SemanticsProfile::default(),
+            cycles: 0,
+            cycle_budget: None,
+            deadline: None,
+            decode_cache: true,
+            detect_loops: false,
+            io,
+            trace: None,
+            profiler: None,
+            call_stack: None,
+            write_protection: WriteProtection::Off,
+            report_self_modifying_code: false,
+            report_uninitialised_reads: false,
+            diagnostics: Vec::new(),
+            interrupts: None,
+            generation: 0,
         }
     }
 
-    pub fn run(&mut self, mut ram: RAM) {
+    /// Puts the machine state back as it was when the CPU was created, keeping the settings, the I/O device and the trace.
+    /// The profiler and the call stack start over, and interrupts are disabled with nothing pending.
+    pub fn reset(&mut self) {
+        self.instruction_pointer = 0;
+        self.instruction_register = 0;
+        self.condition_code = ConditionCode::Eql;
+        self.accumulators = [0; 10];
+        self.registers_initialised = [false; 10];
+        self.stopped = false;
+        self.overflow = false;
+        self.cycles = 0;
+        self.generation += 1;
+        self.diagnostics.clear();
+        if let Some(profiler) = &mut self.profiler {
+            *profiler = Profiler::new();
+        }
+        if let Some(call_stack) = &mut self.call_stack {
+            *call_stack = CallStack::new();
+        }
+        if let Some(interrupts) = &mut self.interrupts {
+            interrupts.reset();
+        }
+    }
+
+    /// Runs until the program stops, faults, or hits one of the limits.
+    pub fn run(&mut self, ram: &mut RAM) -> Result<RunOutcome, CpuFault> {
+        let mut seen = HashSet::new();
+        let observed = self.detect_loops || self.trace.is_some() || self.profiler.is_some() || self.call_stack.is_some() || self.report_uninitialised_reads;
+        let (mut memory_changes, mut memory_reads) = (Vec::new(), Vec::new());
         while !self.stopped {
-            // Get instructions
-
-            let register = ram[self.instruction_pointer];
-            self.instruction_register = register;
-            self.instruction_pointer += 1;
-
-            // Analyse Instruction
-            // 01_23_4_5_6789
-            // fc_mo_a_i_operand
-            let command = if self.instruction_register < 0 {(self.instruction_register + 100_000_000_000) as usize} else {self.instruction_register as usize};
-
-            let fc = num_range!(command, 0; 2);
-            let modus = num_range!(command, 2; 4);
-            let modus1 = modus / 10;
-            let modus2 = modus % 10;
-            let acc = num_range!(command, 4; 5);
-            let ind = num_range!(command, 5; 6);
-            let mut raw_operand: isize = num_range!(command, 6; 10) as isize; // TODO: why s this i8?
-            if raw_operand >= 5_000 { raw_operand -= 10_000 }
-
-            let raw_operand2: isize = match modus2 {
-                1 => raw_operand,//nop
-                2 => raw_operand + self.accumulators[ind],
-                3 => {
-                    self.accumulators[ind] += 1;
-                    raw_operand + self.accumulators[ind]
-                }
-                4 => {
-                    let p = self.accumulators[ind];
-                    self.accumulators[ind] += 1;
-                    raw_operand + p
-                }
-                5 => {
-                    self.accumulators[ind] -= 1;
-                    raw_operand + self.accumulators[ind]
-                }
-                6 => {
-                    let p = self.accumulators[ind];
-                    self.accumulators[ind] += 1;
-                    raw_operand + p
-                }
-                _ => unreachable!()
-            };
-
-            let operand: isize = match modus1 {
-                1 => raw_operand2,
-                2 => ram::address(raw_operand2) as isize,
-                3 => ram[raw_operand2],
-                4 => ram[ram[raw_operand2]],
-                _ => unreachable!() // TODO: don't panic
-            };
-
-            // instruction sets
-
-            match fc.into() {
-                Insn::HIA => {
-                    self.accumulators[acc] = operand;
-                    self.condition_code = ConditionCode::from_number(operand);
-                }
-                Insn::BIG => {
-                    let p = self.accumulators[acc];
-                    ram[operand] = p;
-                    self.condition_code = ConditionCode::from_number(p);
-                }
-                Insn::OPT => {
-                    self.accumulators[acc] += operand;
-                    self.condition_code = ConditionCode::from_number(operand);
-                }
-                Insn::AFT => {
-                    self.accumulators[acc] -= operand;
-                    self.condition_code = ConditionCode::from_number(operand);
-                }
-                Insn::VER => {
-                    self.accumulators[acc] *= operand;
-                    self.condition_code = ConditionCode::from_number(operand);
-                }
-                Insn::DEL => {
-                    self.accumulators[acc] /= operand;
-                    self.condition_code = ConditionCode::from_number(operand);
-                }
-                Insn::MOD => {
-                    self.accumulators[acc] %= operand;
-                    self.condition_code = ConditionCode::from_number(operand);
-                }
-                Insn::VGL => {
-                    self.condition_code = ConditionCode::from_number(self.accumulators[acc] - operand);
-                }
-                Insn::SPR => {
-                    self.instruction_pointer = ram::address(operand);
-                }
-                Insn::VSP => {
-                    if match acc {
-                        1 => self.condition_code == ConditionCode::Eql, /* NUL */
-                        2 => self.condition_code != ConditionCode::Neg, /* NNEG */
-                        3 => self.condition_code != ConditionCode::Pos, /* NPOS */
-                        6 => self.condition_code == ConditionCode::Pos, /* POS */
-                        7 => self.condition_code == ConditionCode::Neg, /* NEG */
-                        8 => self.condition_code != ConditionCode::Eql, /* NNUL */
-                        _ => unreachable!(),
-                    } {
-                        self.instruction_pointer = ram::address(operand);
-                    }
-                }
-                Insn::SBR => {
-                    self.accumulators[9] -= 1;
-                    ram[self.accumulators[9]] = ram::expand(self.instruction_pointer);
-                    self.instruction_pointer = ram::address(operand);
-                }
-                Insn::KTG => {
-                    let ret = self.accumulators[9];
-                    self.accumulators[9] += 1;
-                    self.instruction_pointer = ram::address(ram[ret]);
-                }
-                Insn::LEZ => {}
-                Insn::DRU => {
-                    let variabele = self.accumulators[0];
-                    println!("{:?}", variabele);
-                    self.condition_code = ConditionCode::from_number(self.accumulators[0])
+            if let Some(outcome) = self.check_limits() {
+                return Ok(outcome);
+            }
+            if !observed {
+                self.step_quietly(ram, &mut memory_changes, &mut memory_reads)?;
+                continue;
+            }
+            // A program may be idling until an interrupt arrives
+            let interruptible = matches!(self.interrupts, Some(interrupts) if interrupts.enabled);
+            if self.detect_loops && !interruptible && !seen.insert(self.loop_state()) {
+                return Ok(RunOutcome::ProbableInfiniteLoop { instruction_pointer: self.instruction_pointer });
+            }
+
+            let outcome = self.step(ram)?;
+
+            // A state only recurs if memory and the input stayed the same in between
+            let wrote_memory = outcome.memory_changes.iter().any(|change| change.old != change.new);
+            let read_device = outcome.memory_reads.iter().any(|&address| ram.is_mapped(address));
+            if wrote_memory || read_device || outcome.instruction.fc == FunctionCode::LEZ || seen.len() >= MAX_LOOP_STATES {
+                seen.clear();
+            }
+        }
+        Ok(RunOutcome::Halted)
+    }
+
+    // `u64::is_multiple_of` is too new for the compilers this still builds with
+    #[allow(clippy::manual_is_multiple_of)]
+    pub(crate) fn check_limits(&self) -> Option<RunOutcome> {
+        let instruction_pointer = self.instruction_pointer;
+        if matches!(self.cycle_budget, Some(budget) if self.cycles >= budget) {
+            return Some(RunOutcome::BudgetExhausted { instruction_pointer });
+        }
+        // Reading the clock on every instruction would slow the run down noticeably
+        if self.cycles % DEADLINE_CHECK_INTERVAL == 0 && matches!(self.deadline, Some(deadline) if Instant::now() >= deadline) {
+            return Some(RunOutcome::DeadlineExceeded { instruction_pointer });
+        }
+        None
+    }
+
+    /// The part of the machine state that loop detection compares. RAM is left out,
+    /// because [`run`](CPU::run) forgets all states whenever memory changes.
+    fn loop_state(&self) -> (usize, ConditionCode, [isize; 10], bool) {
+        (self.instruction_pointer, self.condition_code, self.accumulators, self.overflow)
+    }
+
+    /// Fetches and executes a single instruction, even if the CPU has already stopped.
+    ///
+    /// When the instruction faults, the registers, the condition code, the overflow flag, memory and whether the
+    /// instruction counts as [executed](RAM::is_executed) are as they were before, and only the instruction register
+    /// is loaded. What can't be taken back stays: a device that was read or written has seen it, and input `LEZ`
+    /// consumed is gone.
+    pub fn step(&mut self, ram: &mut RAM) -> Result<StepOutcome, CpuFault> {
+        let accumulators = self.accumulators;
+        let condition_code = self.condition_code;
+        let mut memory_changes = Vec::new();
+        let mut memory_reads = Vec::new();
+
+        let (address, insn, operand, interrupt) = self.advance(ram, &mut memory_changes, &mut memory_reads)?;
+        let (registers_read, registers_written) = register_accesses(&insn);
+        if self.report_uninitialised_reads {
+            let site = FaultSite { address, word: self.instruction_register };
+            for &register in registers_read.iter() {
+                if !self.registers_initialised[register.index()] {
+                    self.diagnostics.push(Diagnostic::UninitialisedRegister { site, register });
                 }
-                Insn::NWL => {
-                    println!("\n")
+            }
+            for &read in memory_reads.iter() {
+                // Devices always have something to say
+                if !ram.is_initialised(read) && !ram.is_mapped(read) {
+                    self.diagnostics.push(Diagnostic::UninitialisedMemory { site, address: read });
                 }
-                Insn::DRS => {
-                    println!("unimplemented");
+            }
+        }
+        for register in registers_written.iter() {
+            self.registers_initialised[register.index()] = true;
+        }
+
+        let outcome = StepOutcome {
+            address,
+            word: self.instruction_register,
+            instruction: insn,
+            operand,
+            register_changes: (0..10)
+                .filter(|&r| accumulators[r] != self.accumulators[r])
+                .map(|r| RegisterChange {
+                    register: Register::new(r).unwrap(),
+                    old: accumulators[r],
+                    new: self.accumulators[r],
+                })
+                .collect(),
+            condition_code_change: if condition_code != self.condition_code {
+                Some((condition_code, self.condition_code))
+            } else {
+                None
+            },
+            memory_changes,
+            memory_reads,
+            registers_read,
+            registers_written,
+            interrupt,
+            halted: self.stopped,
+        };
+        if let Some(trace) = &mut self.trace {
+            trace.record(self.cycles, &outcome);
+        }
+        if let Some(profiler) = &mut self.profiler {
+            profiler.record(&outcome);
+        }
+        if let Some(call_stack) = &mut self.call_stack {
+            call_stack.record(&outcome);
+        }
+        Ok(outcome)
+    }
+
+    /// Executes one instruction or takes one interrupt, rolling back on a fault, and lets the devices and
+    /// interrupts know. Returns the address, decoding and effective operand of what ran, and the interrupt line if any.
+    #[allow(clippy::manual_is_multiple_of)] // for the same reason as in `check_limits`
+    fn advance(&mut self, ram: &mut RAM, memory_changes: &mut Vec<MemoryChange>, memory_reads: &mut Vec<usize>) -> Result<(usize, DecodedInstruction, isize, Option<usize>), CpuFault> {
+        let instruction_pointer = self.instruction_pointer;
+        let accumulators = self.accumulators;
+        let condition_code = self.condition_code;
+        let overflow = self.overflow;
+        let was_executed = ram.is_executed(instruction_pointer);
+
+        let interrupt = self.interrupts.and_then(|interrupts| interrupts.next());
+        let executed = match interrupt {
+            Some(line) => self.enter_interrupt(ram, line, memory_changes, memory_reads),
+            None => self.execute(ram, memory_changes, memory_reads),
+        };
+        let (address, insn, operand) = match executed {
+            Ok(executed) => executed,
+            Err(fault) => {
+                // Roll back, so the machine points at the culprit as it was before
+                self.instruction_pointer = instruction_pointer;
+                self.accumulators = accumulators;
+                self.condition_code = condition_code;
+                self.overflow = overflow;
+                for change in memory_changes.iter().rev() {
+                    ram[change.address] = change.old;
+                    ram.set_initialised(change.address, change.was_initialised);
                 }
-                Insn::STP => {
-                    self.stop();
+                ram.set_executed(instruction_pointer, was_executed);
+                return Err(fault);
+            }
+        };
+
+        self.cycles += 1;
+        ram.tick();
+        if let Some(interrupts) = &mut self.interrupts {
+            if let Some(line) = interrupt {
+                interrupts.acknowledge(line);
+            }
+            if matches!(interrupts.timer_interval, Some(interval) if interval > 0 && self.cycles % interval == 0) {
+                interrupts.raise(TIMER_LINE);
+            }
+            ram.poll_interrupts(|line| interrupts.raise(line));
+        }
+        Ok((address, insn, operand, interrupt))
+    }
+
+    /// Does what [`step`](CPU::step) does without describing the step, for when nothing looks at the description.
+    /// The buffers are only there to be reused from one step to the next.
+    fn step_quietly(&mut self, ram: &mut RAM, memory_changes: &mut Vec<MemoryChange>, memory_reads: &mut Vec<usize>) -> Result<(), CpuFault> {
+        memory_changes.clear();
+        memory_reads.clear();
+        let (_, insn, _, _) = self.advance(ram, memory_changes, memory_reads)?;
+        let registers_initialised = &mut self.registers_initialised;
+        visit_register_accesses(&insn, |_| {}, |register| registers_initialised[register.index()] = true);
+        Ok(())
+    }
+
+    /// Executes the instruction at the instruction pointer, returning its address, decoding and effective operand.
+    fn execute(&mut self, ram: &mut RAM, memory_changes: &mut Vec<MemoryChange>, memory_reads: &mut Vec<usize>) -> Result<(usize, DecodedInstruction, isize), CpuFault> {
+        // Get instructions
+        let address = self.instruction_pointer;
+        let register = ram[address];
+        self.instruction_register = register;
+        let site = FaultSite { address, word: register };
+
+        // Analyse Instruction
+        let decoded = if self.decode_cache { ram.decode(address) } else { DecodedInstruction::decode(register) };
+        let insn = decoded.map_err(|e| CpuFault::from_decode_error(site, e))?;
+        ram.mark_executed(address);
+        let acc = insn.acc.index();
+        let ind = insn.index.index();
+        let raw_operand = insn.operand;
+
+        if insn.fc.is_interrupt_extension() && self.interrupts.is_none() {
+            return Err(CpuFault::InvalidOpcode(site, insn.fc.code()));
+        }
+        if insn.fc == FunctionCode::VSP && Condition::from_digit(acc).is_none() {
+            return Err(CpuFault::InvalidCondition(site, acc));
+        }
+
+        let index = self.accumulators[ind];
+        let new_index = match insn.mode2 {
+            Mode2::NoIndex | Mode2::Index => index,
+            Mode2::PreIncrement | Mode2::PostIncrement => self.word_result(index as i128 + 1, site)?,
+            Mode2::PreDecrement | Mode2::PostDecrement => self.word_result(index as i128 - 1, site)?,
+        };
+        let raw_operand2: isize = match insn.mode2 {
+            Mode2::NoIndex => raw_operand,//nop
+            Mode2::Index | Mode2::PostIncrement | Mode2::PostDecrement => self.word_result(raw_operand as i128 + index as i128, site)?,
+            Mode2::PreIncrement | Mode2::PreDecrement => self.word_result(raw_operand as i128 + new_index as i128, site)?,
+        };
+
+        // Memory may hold anything a loader put there, but as a word it reads in ten's complement
+        let operand: isize = match insn.mode1 {
+            Mode1::Value => raw_operand2,
+            Mode1::Address => self.address(raw_operand2, site)? as isize,
+            Mode1::Direct => {
+                let target = self.address(raw_operand2, site)?;
+                memory_reads.push(target);
+                word::wrap(ram.load(target) as i128)
+            }
+            Mode1::Indirect => {
+                let cell = self.address(raw_operand2, site)?;
+                let pointer = self.address(ram.load(cell), site)?;
+                memory_reads.push(cell);
+                memory_reads.push(pointer);
+                word::wrap(ram.load(pointer) as i128)
+            }
+        };
+
+        if let FunctionCode::DEL | FunctionCode::MOD = insn.fc {
+            if operand == 0 {
+                return Err(CpuFault::DivisionByZero(site));
+            }
+        }
+
+        let input = if insn.fc == FunctionCode::LEZ {
+            let number = self.io.read_integer().ok_or(CpuFault::NoInput(site))?;
+            Some(self.word_result(number as i128, site)?)
+        } else {
+            None
+        };
+
+        let string = if insn.fc == FunctionCode::DRS {
+            let start = self.accumulators[0];
+            let string = ram.read_string(start).ok_or(CpuFault::UnterminatedString(site))?;
+            // Every character plus the terminator
+            memory_reads.extend((0..=string.chars().count() as isize).map(|offset| ram::address(start + offset)));
+            Some(string)
+        } else {
+            None
+        };
+
+        self.accumulators[ind] = new_index;
+        self.instruction_pointer = ram::address(address as isize + 1);
+
+        // instruction sets
+
+        match insn.fc {
+            FunctionCode::HIA => {
+                self.accumulators[acc] = operand;
+                self.condition_code = ConditionCode::from_number(operand);
+            }
+            FunctionCode::BIG => {
+                let p = self.accumulators[acc];
+                self.store(ram, memory_changes, operand, p, site)?;
+                self.condition_code = ConditionCode::from_number(p);
+            }
+            FunctionCode::OPT => {
+                let result = self.word_result(self.accumulators[acc] as i128 + operand as i128, site)?;
+                self.set_arithmetic_result(acc, operand, result);
+            }
+            FunctionCode::AFT => {
+                let result = self.word_result(self.accumulators[acc] as i128 - operand as i128, site)?;
+                self.set_arithmetic_result(acc, operand, result);
+            }
+            FunctionCode::VER => {
+                let result = self.word_result(self.accumulators[acc] as i128 * operand as i128, site)?;
+                self.set_arithmetic_result(acc, operand, result);
+            }
+            FunctionCode::DEL => {
+                let (quotient, _) = self.semantics.divide(self.accumulators[acc] as i128, operand as i128);
+                let result = self.word_result(quotient, site)?;
+                self.set_arithmetic_result(acc, operand, result);
+            }
+            FunctionCode::MOD => {
+                let (_, remainder) = self.semantics.divide(self.accumulators[acc] as i128, operand as i128);
+                let result = self.word_result(remainder, site)?;
+                self.set_arithmetic_result(acc, operand, result);
+            }
+            FunctionCode::VGL => {
+                self.condition_code = ConditionCode::from_number(self.accumulators[acc] - operand);
+            }
+            FunctionCode::SPR => {
+                self.instruction_pointer = self.address(operand, site)?;
+            }
+            FunctionCode::VSP => {
+                if match Condition::from_digit(acc).unwrap() { // checked above
+                    Condition::NUL => self.condition_code == ConditionCode::Eql,
+                    Condition::NNEG => self.condition_code != ConditionCode::Neg,
+                    Condition::NPOS => self.condition_code != ConditionCode::Pos,
+                    Condition::POS => self.condition_code == ConditionCode::Pos,
+                    Condition::NEG => self.condition_code == ConditionCode::Neg,
+                    Condition::NNUL => self.condition_code != ConditionCode::Eql,
+                } {
+                    self.instruction_pointer = self.address(operand, site)?;
                 }
             }
+            FunctionCode::SBR => {
+                self.accumulators[9] = self.word_result(self.accumulators[9] as i128 - 1, site)?;
+                let target = self.address(operand, site)?;
+                self.check_push(ram, self.address(self.accumulators[9], site)?, site)?;
+                self.store(ram, memory_changes, self.accumulators[9], ram::expand(self.instruction_pointer), site)?;
+                self.instruction_pointer = target;
+            }
+            FunctionCode::KTG => {
+                let ret = self.address(self.accumulators[9], site)?;
+                memory_reads.push(ret);
+                self.accumulators[9] = self.word_result(self.accumulators[9] as i128 + 1, site)?;
+                self.instruction_pointer = self.address(ram.load(ret), site)?;
+                self.check_return(site)?;
+            }
+            FunctionCode::KTO => {
+                let top = self.accumulators[9];
+                let (condition_code, return_address) = (self.address(top, site)?, self.address(top + 1, site)?);
+                memory_reads.push(condition_code);
+                memory_reads.push(return_address);
+                self.accumulators[9] = self.word_result(top as i128 + 2, site)?;
+                self.condition_code = ConditionCode::from_number(ram.load(condition_code));
+                self.instruction_pointer = self.address(ram.load(return_address), site)?;
+                self.check_return(site)?;
+                self.interrupts.as_mut().unwrap().enabled = true; // checked above
+            }
+            FunctionCode::OBA => {
+                self.interrupts.as_mut().unwrap().enabled = true;
+            }
+            FunctionCode::OBU => {
+                self.interrupts.as_mut().unwrap().enabled = false;
+            }
+            FunctionCode::LEZ => {
+                let number = input.unwrap();
+                self.accumulators[0] = number;
+                self.condition_code = ConditionCode::from_number(number);
+            }
+            FunctionCode::DRU => {
+                let variabele = self.accumulators[0];
+                self.io.print_number(variabele);
+                self.condition_code = ConditionCode::from_number(self.accumulators[0])
+            }
+            FunctionCode::NWL => {
+                self.io.print_newline();
+            }
+            FunctionCode::DRS => {
+                self.io.print_string(&string.unwrap());
+            }
+            FunctionCode::STP => {
+                self.stop();
+            }
+        }
+
+        Ok((address, insn, operand))
+    }
+
+    /// Takes an interrupt instead of executing an instruction. This is presented as an `SBR` to the handler.
+    fn enter_interrupt(&mut self, ram: &mut RAM, line: usize, memory_changes: &mut Vec<MemoryChange>, memory_reads: &mut Vec<usize>) -> Result<(usize, DecodedInstruction, isize), CpuFault> {
+        let address = self.instruction_pointer;
+        let vector = ram::address((self.interrupts.unwrap().vector_table + line) as isize);
+        memory_reads.push(vector);
+        let handler = ram::address(ram.load(vector));
+        let insn = DecodedInstruction {
+            fc: FunctionCode::SBR,
+            mode1: Mode1::Address,
+            mode2: Mode2::NoIndex,
+            acc: Register::R0,
+            index: Register::R0,
+            operand: ram::expand(handler),
+        };
+        self.instruction_register = insn.encode();
+        let site = FaultSite { address, word: self.instruction_register };
+
+        let condition_code = match self.condition_code {
+            ConditionCode::Pos => 1,
+            ConditionCode::Eql => 0,
+            ConditionCode::Neg => -1,
+        };
+        self.accumulators[9] = self.word_result(self.accumulators[9] as i128 - 2, site)?;
+        self.check_push(ram, self.address(self.accumulators[9] + 1, site)?, site)?;
+        self.check_push(ram, self.address(self.accumulators[9], site)?, site)?;
+        self.store(ram, memory_changes, self.accumulators[9] + 1, ram::expand(address), site)?;
+        self.store(ram, memory_changes, self.accumulators[9], condition_code, site)?;
+        self.instruction_pointer = handler;
+        Ok((address, insn, handler as isize))
+    }
+
+    /// Writes a word to RAM, recording the change and checking it against write protection.
+    fn store(&mut self, ram: &mut RAM, changes: &mut Vec<MemoryChange>, address: isize, value: isize, site: FaultSite) -> Result<(), CpuFault> {
+        let address = self.address(address, site)?;
+        if ram.is_write_protected(address) {
+            match self.write_protection {
+                WriteProtection::Off => {}
+                WriteProtection::Warn => self.diagnostics.push(Diagnostic::WriteToProtected { site, address }),
+                WriteProtection::Fault => return Err(CpuFault::WriteToProtected(site, address)),
+            }
+        }
+        if self.report_self_modifying_code && ram.is_executed(address) {
+            self.diagnostics.push(Diagnostic::SelfModifyingCode { site, address });
+        }
+        changes.push(MemoryChange { address, old: ram[address], new: value, was_initialised: ram.is_initialised(address) });
+        ram.store(address, value);
+        Ok(())
+    }
+
+    /// With a call stack, refuses to push onto the program.
+    fn check_push(&self, ram: &RAM, address: usize, site: FaultSite) -> Result<(), CpuFault> {
+        if self.call_stack.is_some() && ram.is_loaded(address) {
+            return Err(CpuFault::StackCollision(site, address));
+        }
+        Ok(())
+    }
+
+    /// With a call stack, refuses to return anywhere but to the innermost pending call.
+    fn check_return(&self, site: FaultSite) -> Result<(), CpuFault> {
+        if let Some(call_stack) = &self.call_stack {
+            let expected = call_stack.expected_return();
+            if expected != Some(self.instruction_pointer) {
+                return Err(CpuFault::ReturnMismatch { site, expected, found: self.instruction_pointer });
+            }
+        }
+        Ok(())
+    }
+
+    /// Turns a number into an address, as [`SemanticsProfile::addresses`] says.
+    fn address(&self, number: isize, site: FaultSite) -> Result<usize, CpuFault> {
+        match self.semantics.addresses {
+            AddressRange::Fault if !(-word::OPERAND_MODULUS / 2..10_000).contains(&number) => Err(CpuFault::AddressOutOfRange(site, number)),
+            _ => Ok(ram::address(number)),
+        }
+    }
+
+    /// Puts the result of `OPT`, `AFT`, `VER`, `DEL` or `MOD` in the accumulator, and sets the condition code
+    /// as [`SemanticsProfile::condition_code`] says.
+    fn set_arithmetic_result(&mut self, acc: usize, operand: isize, result: isize) {
+        self.accumulators[acc] = result;
+        self.condition_code = match self.semantics.condition_code {
+            ConditionCodeSource::Operand => ConditionCode::from_number(operand),
+            ConditionCodeSource::Result => ConditionCode::from_number(result),
+        };
+    }
+
+    /// Brings a result into the range of a word, raising the overflow flag if it wasn't.
+    fn word_result(&mut self, value: i128, site: FaultSite) -> Result<isize, CpuFault> {
+        if word::fits(value) {
+            return Ok(value as isize);
+        }
+        self.overflow = true;
+        match self.overflow_mode {
+            OverflowMode::Wrap => Ok(word::wrap(value)),
+            OverflowMode::Fault => Err(CpuFault::Overflow(site)),
         }
     }
 
@@ -169,52 +582,131 @@ impl CPU {
     }
 }
 
-pub enum Insn {
-    HIA = 11,
-    BIG = 12,
-    OPT = 21,
-    AFT = 22,
-    VER = 23,
-    DEL = 24,
-    MOD = 25,
-    VGL = 31,
-    SPR = 32,
-    VSP = 33,
-    SBR = 41,
-    KTG = 42,
-    LEZ = 71,
-    DRU = 72,
-    NWL = 73,
-    DRS = 74,
-    STP = 99,
+impl Default for CPU {
+    fn default() -> Self {
+        CPU::new()
+    }
+}
+
+/// What the CPU does with a result that doesn't fit in a word.
+/// Either way, the [overflow flag](CPU::overflow) is raised.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OverflowMode {
+    /// Wrap around, as the ten's complement hardware does.
+    Wrap,
+    /// Stop with [`CpuFault::Overflow`].
+    Fault,
 }
 
-impl From<usize> for Insn {
-    fn from(n: usize) -> Self {
-        match n {
-            11 => Insn::HIA,
-            12 => Insn::BIG,
-            21 => Insn::OPT,
-            22 => Insn::AFT,
-            23 => Insn::VER,
-            24 => Insn::DEL,
-            25 => Insn::MOD,
-            31 => Insn::VGL,
-            32 => Insn::SPR,
-            33 => Insn::VSP,
-            41 => Insn::SBR,
-            42 => Insn::KTG,
-            71 => Insn::LEZ,
-            72 => Insn::DRU,
-            73 => Insn::NWL,
-            74 => Insn::DRS,
-            99 => Insn::STP,
-            _ => unreachable!()
+
+/// The accumulators an instruction reads and writes, in that order, not counting the fetch.
+fn register_accesses(insn: &DecodedInstruction) -> (Vec<Register>, Vec<Register>) {
+    let mut read = Vec::new();
+    let mut written = Vec::new();
+    visit_register_accesses(insn, |register| read.push(register), |register| written.push(register));
+    (read, written)
+}
+
+/// Calls `read` and `written` for the accumulators an instruction reads and writes, as in [`register_accesses`].
+fn visit_register_accesses<R: FnMut(Register), W: FnMut(Register)>(insn: &DecodedInstruction, mut read: R, mut written: W) {
+    if insn.mode2 != Mode2::NoIndex {
+        read(insn.index);
+    }
+    if !matches!(insn.mode2, Mode2::NoIndex | Mode2::Index) {
+        written(insn.index);
+    }
+    match insn.fc {
+        FunctionCode::HIA => written(insn.acc),
+        FunctionCode::BIG | FunctionCode::VGL => read(insn.acc),
+        FunctionCode::OPT | FunctionCode::AFT | FunctionCode::VER | FunctionCode::DEL | FunctionCode::MOD => {
+            read(insn.acc);
+            written(insn.acc);
+        }
+        FunctionCode::SBR | FunctionCode::KTG | FunctionCode::KTO => {
+            read(Register::R9);
+            written(Register::R9);
         }
+        FunctionCode::LEZ => written(Register::R0),
+        FunctionCode::DRU | FunctionCode::DRS => read(Register::R0),
+        FunctionCode::SPR | FunctionCode::VSP | FunctionCode::NWL | FunctionCode::STP | FunctionCode::OBA | FunctionCode::OBU => {}
     }
 }
 
-#[derive(PartialEq)]
+/// What the CPU does with a store into a [write-protected](RAM::write_protect) cell.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WriteProtection {
+    /// Store anyway, as the hardware does.
+    Off,
+    /// Store anyway, adding a [`Diagnostic::WriteToProtected`].
+    Warn,
+    /// Stop with [`CpuFault::WriteToProtected`].
+    Fault,
+}
+
+/// How many instructions pass between two looks at the clock for [`CPU::deadline`].
+const DEADLINE_CHECK_INTERVAL: u64 = 1024;
+/// How many states loop detection remembers before it starts over, to bound its memory use.
+const MAX_LOOP_STATES: usize = 100_000;
+
+/// Why [`CPU::run`] returned.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RunOutcome {
+    /// The program executed `STP`.
+    Halted,
+    /// [`CPU::cycle_budget`] instructions have been executed.
+    BudgetExhausted { instruction_pointer: usize },
+    /// [`CPU::deadline`] has passed.
+    DeadlineExceeded { instruction_pointer: usize },
+    /// The machine is back in a state it was in before, without having written memory, read input or read a device since,
+    /// so it will keep repeating itself.
+    ProbableInfiniteLoop { instruction_pointer: usize },
+}
+
+/// What a single call to [`CPU::step`] did.
+#[derive(Debug, Clone)]
+pub struct StepOutcome {
+    /// The address the instruction was fetched from.
+    pub address: usize,
+    /// The instruction as it was found in memory.
+    pub word: isize,
+    pub instruction: DecodedInstruction,
+    /// The operand after indexing and applying the interpretation: the value used, or the address jumped to or stored at.
+    pub operand: isize,
+    /// The accumulators whose value differs after the instruction.
+    pub register_changes: Vec<RegisterChange>,
+    /// The condition code before and after the instruction, if it changed.
+    pub condition_code_change: Option<(ConditionCode, ConditionCode)>,
+    /// Every RAM cell the instruction wrote, in order.
+    pub memory_changes: Vec<MemoryChange>,
+    /// Every RAM cell the instruction read as data, in order. The instruction fetch is not included.
+    pub memory_reads: Vec<usize>,
+    /// The accumulators the instruction read, including its index register.
+    pub registers_read: Vec<Register>,
+    /// The accumulators the instruction wrote, whether or not their value changed.
+    pub registers_written: Vec<Register>,
+    /// The interrupt line taken instead of executing an instruction, see [`Interrupts`].
+    pub interrupt: Option<usize>,
+    /// Whether the CPU is stopped after the instruction.
+    pub halted: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RegisterChange {
+    pub register: Register,
+    pub old: isize,
+    pub new: isize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MemoryChange {
+    pub address: usize,
+    pub old: isize,
+    pub new: isize,
+    /// Whether the cell was [initialised](RAM::is_initialised) before the store.
+    pub was_initialised: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum ConditionCode {
     Pos,
     Eql,
@@ -231,4 +723,294 @@ impl ConditionCode {
             ConditionCode::Pos
         }
     }
-}
\ No newline at end of file
+
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            ConditionCode::Pos => "POS",
+            ConditionCode::Eql => "EQL",
+            ConditionCode::Neg => "NEG",
+        }
+    }
+
+    pub fn from_mnemonic(mnemonic: &str) -> Option<Self> {
+        match mnemonic {
+            "POS" => Some(ConditionCode::Pos),
+            "EQL" => Some(ConditionCode::Eql),
+            "NEG" => Some(ConditionCode::Neg),
+            _ => None,
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use drama_isa::word::encode;
+
+    use super::*;
+    use crate::io::ScriptedIo;
+
+    /// An instruction without indexing.
+    fn insn(fc: FunctionCode, mode1: Mode1, acc: Register, operand: isize) -> isize {
+        encode(fc, mode1, Mode2::NoIndex, acc, Register::R0, operand)
+    }
+
+    fn stp() -> isize {
+        word::encode_without_operand(FunctionCode::STP)
+    }
+
+    /// A CPU without console, and RAM holding `program` from address 0.
+    fn machine(program: &[isize]) -> (CPU, RAM) {
+        let mut ram = RAM::new();
+        let image: Vec<(usize, isize)> = program.iter().copied().enumerate().collect();
+        ram.load_image(&image).unwrap();
+        (CPU::with_io(Box::new(ScriptedIo::default())), ram)
+    }
+
+    #[test]
+    fn step_continues_after_an_instruction() {
+        let (mut cpu, mut ram) = machine(&[insn(FunctionCode::HIA, Mode1::Value, Register::R1, -7), stp()]);
+        let outcome = cpu.step(&mut ram).unwrap();
+        assert!(!outcome.halted && !cpu.stopped);
+        assert_eq!(outcome.address, 0);
+        assert_eq!(outcome.operand, -7);
+        assert_eq!(outcome.register_changes, vec![RegisterChange { register: Register::R1, old: 0, new: -7 }]);
+        assert_eq!(outcome.condition_code_change, Some((ConditionCode::Eql, ConditionCode::Neg)));
+        assert_eq!(cpu.instruction_pointer, 1);
+        assert_eq!(cpu.accumulators[1], -7);
+        assert_eq!(cpu.cycles, 1);
+    }
+
+    #[test]
+    fn step_halts_on_stp() {
+        let (mut cpu, mut ram) = machine(&[insn(FunctionCode::HIA, Mode1::Value, Register::R1, 3), stp()]);
+        cpu.step(&mut ram).unwrap();
+        let outcome = cpu.step(&mut ram).unwrap();
+        assert!(outcome.halted && cpu.stopped);
+        assert_eq!(outcome.address, 1);
+        assert!(outcome.register_changes.is_empty());
+        assert_eq!(cpu.instruction_pointer, 2);
+        assert_eq!(cpu.accumulators[1], 3);
+        assert_eq!(cpu.run(&mut ram), Ok(RunOutcome::Halted));
+        assert_eq!(cpu.cycles, 2);
+    }
+
+    #[test]
+    fn step_reports_a_fault_without_moving_on() {
+        let (mut cpu, mut ram) = machine(&[insn(FunctionCode::HIA, Mode1::Value, Register::R1, 3), 42]);
+        cpu.step(&mut ram).unwrap();
+        let fault = cpu.step(&mut ram).unwrap_err();
+        assert_eq!(fault, CpuFault::DataFetch(FaultSite { address: 1, word: 42 }));
+        assert!(!cpu.stopped);
+        assert_eq!(cpu.instruction_pointer, 1);
+        assert_eq!(cpu.instruction_register, 42);
+        assert_eq!(cpu.accumulators[1], 3);
+        assert_eq!(cpu.cycles, 1);
+        // Stepping again runs into the same fault
+        assert_eq!(cpu.step(&mut ram).unwrap_err(), fault);
+    }
+
+    #[test]
+    fn division_by_zero_faults_and_leaves_the_machine_untouched() {
+        let (mut cpu, mut ram) = machine(&[insn(FunctionCode::HIA, Mode1::Value, Register::R1, 8), insn(FunctionCode::DEL, Mode1::Value, Register::R1, 0)]);
+        cpu.step(&mut ram).unwrap();
+        let condition_code = cpu.condition_code;
+        assert!(matches!(cpu.step(&mut ram), Err(CpuFault::DivisionByZero(FaultSite { address: 1, .. }))));
+        assert_eq!(cpu.instruction_pointer, 1);
+        assert_eq!(cpu.accumulators[1], 8);
+        assert_eq!(cpu.condition_code, condition_code);
+        assert!(ram.is_executed(0));
+        assert!(!ram.is_executed(1));
+    }
+
+    #[test]
+    fn an_unknown_function_code_faults() {
+        // Function code 13 sits between BIG and OPT
+        let word = insn(FunctionCode::HIA, Mode1::Value, Register::R1, 5) + 2 * 100_000_000;
+        let (mut cpu, mut ram) = machine(&[word]);
+        assert_eq!(cpu.step(&mut ram).unwrap_err(), CpuFault::InvalidOpcode(FaultSite { address: 0, word }, 13));
+        assert_eq!(cpu.instruction_pointer, 0);
+        assert_eq!(cpu.accumulators[1], 0);
+        assert!(!ram.is_executed(0));
+    }
+
+    #[test]
+    fn an_address_outside_of_memory_faults_only_when_the_semantics_say_so() {
+        let program = [
+            insn(FunctionCode::HIA, Mode1::Value, Register::R2, 4000),
+            insn(FunctionCode::OPT, Mode1::Value, Register::R2, 4000),
+            encode(FunctionCode::HIA, Mode1::Address, Mode2::Index, Register::R1, Register::R2, 3000),
+        ];
+        let (mut cpu, mut ram) = machine(&program);
+        cpu.semantics = SemanticsProfile::REFERENCE_COMPATIBLE;
+        cpu.step(&mut ram).unwrap();
+        cpu.step(&mut ram).unwrap();
+        assert!(matches!(cpu.step(&mut ram), Err(CpuFault::AddressOutOfRange(FaultSite { address: 2, .. }, 11_000))));
+        assert_eq!(cpu.instruction_pointer, 2);
+        assert_eq!(cpu.accumulators[1], 0);
+
+        cpu.semantics = SemanticsProfile::THIS_PROJECT;
+        cpu.step(&mut ram).unwrap();
+        assert_eq!(cpu.accumulators[1], 1000);
+    }
+
+    #[test]
+    fn a_fault_rolls_back_an_index_register() {
+        // BIG R1, 50(R2+) increments R2, then runs into the protected cell
+        let (mut cpu, mut ram) = machine(&[encode(FunctionCode::BIG, Mode1::Address, Mode2::PostIncrement, Register::R1, Register::R2, 50)]);
+        cpu.write_protection = WriteProtection::Fault;
+        ram.write_protect(50);
+        assert!(matches!(cpu.step(&mut ram), Err(CpuFault::WriteToProtected(_, 50))));
+        assert_eq!(cpu.accumulators[2], 0);
+        assert_eq!(cpu.instruction_pointer, 0);
+        assert!(!ram.is_initialised(50));
+    }
+
+    #[test]
+    fn a_fault_rolls_back_memory() {
+        // Taking the interrupt stores the return address at 99, then faults on the condition code at 98
+        let (mut cpu, mut ram) = machine(&[stp()]);
+        ram.load_image(&[(200, 7)]).unwrap();
+        let mut interrupts = Interrupts::new(200);
+        interrupts.enabled = true;
+        interrupts.raise(0);
+        cpu.interrupts = Some(interrupts);
+        cpu.accumulators[9] = 100;
+        cpu.write_protection = WriteProtection::Fault;
+        ram.write_protect(98);
+        assert!(matches!(cpu.step(&mut ram), Err(CpuFault::WriteToProtected(_, 98))));
+        assert_eq!(ram[99usize], 0);
+        assert!(!ram.is_initialised(99));
+        assert_eq!(cpu.accumulators[9], 100);
+        assert_eq!(cpu.instruction_pointer, 0);
+        assert!(cpu.interrupts.unwrap().is_pending(0));
+    }
+
+    /// Runs `fc R1, operand` with `R1` holding `value`.
+    fn arithmetic(overflow_mode: OverflowMode, value: isize, fc: FunctionCode, operand: isize) -> (CPU, Result<StepOutcome, CpuFault>) {
+        let (mut cpu, mut ram) = machine(&[insn(fc, Mode1::Value, Register::R1, operand)]);
+        cpu.overflow_mode = overflow_mode;
+        cpu.accumulators[1] = value;
+        let result = cpu.step(&mut ram);
+        (cpu, result)
+    }
+
+    #[test]
+    fn results_at_the_edge_of_a_word_fit() {
+        let cases = [
+            (word::WORD_MAX - 1, FunctionCode::OPT, 1, word::WORD_MAX),
+            (word::WORD_MIN + 1, FunctionCode::AFT, 1, word::WORD_MIN),
+            (-2_500_000_000, FunctionCode::VER, 2, word::WORD_MIN),
+        ];
+        for (value, fc, operand, expected) in cases {
+            let (cpu, result) = arithmetic(OverflowMode::Fault, value, fc, operand);
+            assert!(result.is_ok());
+            assert!(!cpu.overflow);
+            assert_eq!(cpu.accumulators[1], expected);
+        }
+    }
+
+    #[test]
+    fn overflow_wraps_around_in_wrap_mode() {
+        let (cpu, _) = arithmetic(OverflowMode::Wrap, word::WORD_MAX, FunctionCode::OPT, 1);
+        assert_eq!(cpu.accumulators[1], word::WORD_MIN);
+        assert!(cpu.overflow);
+        let (cpu, _) = arithmetic(OverflowMode::Wrap, word::WORD_MIN, FunctionCode::AFT, 1);
+        assert_eq!(cpu.accumulators[1], word::WORD_MAX);
+        assert!(cpu.overflow);
+        let (cpu, _) = arithmetic(OverflowMode::Wrap, word::WORD_MIN, FunctionCode::VER, -1);
+        assert_eq!(cpu.accumulators[1], word::WORD_MIN);
+        assert!(cpu.overflow);
+    }
+
+    #[test]
+    fn overflow_faults_in_fault_mode() {
+        for (value, fc, operand) in [(word::WORD_MAX, FunctionCode::OPT, 1), (word::WORD_MIN, FunctionCode::AFT, 1), (word::WORD_MIN, FunctionCode::VER, -1)] {
+            let (cpu, result) = arithmetic(OverflowMode::Fault, value, fc, operand);
+            assert!(matches!(result, Err(CpuFault::Overflow(FaultSite { address: 0, .. }))));
+            assert_eq!(cpu.accumulators[1], value);
+            assert!(!cpu.overflow);
+        }
+    }
+
+    #[test]
+    fn the_cycle_budget_stops_after_exactly_that_many_steps() {
+        let (mut cpu, mut ram) = machine(&[insn(FunctionCode::OPT, Mode1::Value, Register::R1, 1); 10]);
+        cpu.cycle_budget = Some(4);
+        assert_eq!(cpu.run(&mut ram), Ok(RunOutcome::BudgetExhausted { instruction_pointer: 4 }));
+        assert_eq!(cpu.cycles, 4);
+        assert_eq!(cpu.accumulators[1], 4);
+        // The budget counts all steps so far, so running on stops right away
+        assert_eq!(cpu.run(&mut ram), Ok(RunOutcome::BudgetExhausted { instruction_pointer: 4 }));
+        assert_eq!(cpu.cycles, 4);
+    }
+
+    #[test]
+    fn a_jump_to_itself_is_a_probable_infinite_loop() {
+        // l: SPR l
+        let program = [insn(FunctionCode::OPT, Mode1::Value, Register::R1, 1), insn(FunctionCode::SPR, Mode1::Address, Register::R0, 1)];
+        let (mut cpu, mut ram) = machine(&program);
+        cpu.detect_loops = true;
+        cpu.cycle_budget = Some(1000);
+        assert_eq!(cpu.run(&mut ram), Ok(RunOutcome::ProbableInfiniteLoop { instruction_pointer: 1 }));
+        assert!(cpu.cycles < 1000);
+
+        let (mut cpu, mut ram) = machine(&program);
+        cpu.cycle_budget = Some(1000);
+        assert_eq!(cpu.run(&mut ram), Ok(RunOutcome::BudgetExhausted { instruction_pointer: 1 }));
+    }
+
+    #[test]
+    fn an_interrupt_runs_its_handler_and_kto_returns() {
+        let r = |n| Register::new(n).unwrap();
+        let (mut cpu, mut ram) = machine(&[
+            word::encode_without_operand(FunctionCode::OBA),
+            insn(FunctionCode::OPT, Mode1::Value, r(1), 1),
+            stp(),
+        ]);
+        // The handler for line 3 counts in R2
+        ram.load_image(&[
+            (50, insn(FunctionCode::OPT, Mode1::Value, r(2), 1)),
+            (51, word::encode_without_operand(FunctionCode::KTO)),
+            (203, 50),
+        ]).unwrap();
+        cpu.interrupts = Some(Interrupts::new(200));
+        cpu.accumulators[9] = 100;
+
+        cpu.step(&mut ram).unwrap();
+        cpu.interrupts.as_mut().unwrap().raise(3);
+        let outcome = cpu.step(&mut ram).unwrap();
+        assert_eq!(outcome.interrupt, Some(3));
+        assert_eq!(outcome.address, 1);
+        assert_eq!(cpu.instruction_pointer, 50);
+        assert_eq!(cpu.accumulators[9], 98);
+        assert_eq!((ram[98usize], ram[99usize]), (0, 1));
+        let interrupts = cpu.interrupts.unwrap();
+        assert!(!interrupts.enabled && !interrupts.is_pending(3));
+
+        cpu.step(&mut ram).unwrap();
+        assert_eq!(cpu.condition_code, ConditionCode::Pos);
+        cpu.step(&mut ram).unwrap();
+        assert_eq!(cpu.instruction_pointer, 1);
+        assert_eq!(cpu.accumulators[9], 100);
+        assert_eq!(cpu.condition_code, ConditionCode::Eql);
+        assert!(cpu.interrupts.unwrap().enabled);
+
+        assert_eq!(cpu.run(&mut ram), Ok(RunOutcome::Halted));
+        assert_eq!((cpu.accumulators[1], cpu.accumulators[2]), (1, 1));
+    }
+
+    #[test]
+    fn the_timer_raises_its_line_every_interval() {
+        let (mut cpu, mut ram) = machine(&[insn(FunctionCode::OPT, Mode1::Value, Register::R1, 1); 7]);
+        let mut interrupts = Interrupts::new(200);
+        interrupts.timer_interval = Some(3);
+        cpu.interrupts = Some(interrupts);
+        for cycle in 1..=7 {
+            cpu.step(&mut ram).unwrap();
+            // Interrupts are disabled, so the line stays pending once raised
+            assert_eq!(cpu.interrupts.unwrap().is_pending(TIMER_LINE), cycle >= 3, "after {} cycles", cycle);
+        }
+        // Without the extension, its instructions are invalid
+        let (mut cpu, mut ram) = machine(&[word::encode_without_operand(FunctionCode::OBA)]);
+        assert!(matches!(cpu.step(&mut ram), Err(CpuFault::InvalidOpcode(_, 44))));
+    }
+}
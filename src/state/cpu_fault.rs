@@ -0,0 +1,100 @@
+use std::fmt::Formatter;
+
+use drama_isa::{word, DecodeError};
+
+/// The instruction a fault happened on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FaultSite {
+    pub address: usize,
+    pub word: isize,
+}
+
+/// A fault raised by a program, such as executing a word that isn't an instruction.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CpuFault {
+    /// The word has function code `00`, as does any small number or never-written cell.
+    /// Usually the program ran past its `STP` or jumped into its data.
+    DataFetch(FaultSite),
+    InvalidOpcode(FaultSite, usize),
+    InvalidAddressingMode {
+        site: FaultSite,
+        mode1: usize,
+        mode2: usize,
+    },
+    InvalidCondition(FaultSite, usize),
+    DivisionByZero(FaultSite),
+    /// A result didn't fit in a word, with [`OverflowMode::Fault`](crate::state::cpu::OverflowMode::Fault).
+    Overflow(FaultSite),
+    /// `LEZ` found its input device exhausted.
+    NoInput(FaultSite),
+    /// `DRS` found no `0` word after the start of its string.
+    UnterminatedString(FaultSite),
+    /// `SBR` or an interrupt would push onto a cell holding the program. Only detected with a [`CallStack`](crate::call_stack::CallStack).
+    StackCollision(FaultSite, usize),
+    /// A store into a [write-protected](crate::state::ram::RAM::write_protect) cell, with
+    /// [`WriteProtection::Fault`](crate::state::cpu::WriteProtection::Fault).
+    WriteToProtected(FaultSite, usize),
+    /// An address outside of memory, with [`AddressRange::Fault`](crate::state::semantics::AddressRange::Fault).
+    AddressOutOfRange(FaultSite, isize),
+    /// `KTG` or `KTO` popped a different address than the pending call returns to, or there was no pending call.
+    /// Only detected with a [`CallStack`](crate::call_stack::CallStack).
+    ReturnMismatch {
+        site: FaultSite,
+        expected: Option<usize>,
+        found: usize,
+    },
+}
+
+impl CpuFault {
+    pub(crate) fn from_decode_error(site: FaultSite, error: DecodeError) -> Self {
+        match error {
+            DecodeError::InvalidFunctionCode(0) => CpuFault::DataFetch(site),
+            DecodeError::InvalidFunctionCode(fc) => CpuFault::InvalidOpcode(site, fc),
+            DecodeError::InvalidMode1(_) | DecodeError::InvalidMode2(_) => {
+                let fields = word::split(site.word);
+                CpuFault::InvalidAddressingMode { site, mode1: fields.mode1, mode2: fields.mode2 }
+            }
+        }
+    }
+
+    pub fn get_site(&self) -> &FaultSite {
+        match self {
+            CpuFault::DataFetch(site) => site,
+            CpuFault::InvalidOpcode(site, _) => site,
+            CpuFault::InvalidAddressingMode { site, .. } => site,
+            CpuFault::InvalidCondition(site, _) => site,
+            CpuFault::DivisionByZero(site) => site,
+            CpuFault::Overflow(site) => site,
+            CpuFault::NoInput(site) => site,
+            CpuFault::UnterminatedString(site) => site,
+            CpuFault::StackCollision(site, _) => site,
+            CpuFault::WriteToProtected(site, _) => site,
+            CpuFault::AddressOutOfRange(site, _) => site,
+            CpuFault::ReturnMismatch { site, .. } => site,
+        }
+    }
+}
+
+impl std::error::Error for CpuFault {}
+
+impl std::fmt::Display for CpuFault {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let site = self.get_site();
+        write!(f, "At address {:04} [{:010}]: ", site.address, site.word)?;
+        match self {
+            CpuFault::DataFetch(_) => write!(f, "Tried to execute a data word"),
+            CpuFault::InvalidOpcode(_, fc) => write!(f, "`{:02}` is not a function code", fc),
+            CpuFault::InvalidAddressingMode { mode1, mode2, .. } => write!(f, "`{}{}` is not a valid addressing mode", mode1, mode2),
+            CpuFault::InvalidCondition(_, condition) => write!(f, "`{}` is not a jump condition", condition),
+            CpuFault::DivisionByZero(_) => write!(f, "Division by zero"),
+            CpuFault::Overflow(_) => write!(f, "The result does not fit in a word"),
+            CpuFault::NoInput(_) => write!(f, "`LEZ` has no input left to read"),
+            CpuFault::UnterminatedString(_) => write!(f, "`DRS` found no end to the string at the address in R0"),
+            CpuFault::StackCollision(_, address) => write!(f, "The stack ran into the program at address {:04}", address),
+            CpuFault::WriteToProtected(_, address) => write!(f, "Tried to write to the write-protected cell {:04}", address),
+            CpuFault::AddressOutOfRange(_, address) => write!(f, "{} is not an address", address),
+            CpuFault::ReturnMismatch { expected: Some(expected), found, .. } => write!(f, "Returned to {:04}, but the pending call returns to {:04}", found, expected),
+            CpuFault::ReturnMismatch { expected: None, found, .. } => write!(f, "Returned to {:04} without a pending call", found),
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use std::fmt::Formatter;
+
+use drama_isa::Register;
+
+use crate::state::cpu_fault::FaultSite;
+
+/// Something suspicious a program did that doesn't stop it, collected in [`CPU::diagnostics`](crate::state::cpu::CPU::diagnostics).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Diagnostic {
+    /// A store into a [write-protected](crate::state::ram::RAM::write_protect) cell, with [`WriteProtection::Warn`](crate::state::cpu::WriteProtection::Warn).
+    WriteToProtected { site: FaultSite, address: usize },
+    /// A store into a cell that has been executed before, with [`CPU::report_self_modifying_code`](crate::state::cpu::CPU::report_self_modifying_code).
+    SelfModifyingCode { site: FaultSite, address: usize },
+    /// A read of a cell nothing has written, with [`CPU::report_uninitialised_reads`](crate::state::cpu::CPU::report_uninitialised_reads).
+    UninitialisedMemory { site: FaultSite, address: usize },
+    /// A read of a register no instruction has written, with [`CPU::report_uninitialised_reads`](crate::state::cpu::CPU::report_uninitialised_reads).
+    UninitialisedRegister { site: FaultSite, register: Register },
+}
+
+impl Diagnostic {
+    pub fn get_site(&self) -> &FaultSite {
+        match self {
+            Diagnostic::WriteToProtected { site, .. } => site,
+            Diagnostic::SelfModifyingCode { site, .. } => site,
+            Diagnostic::UninitialisedMemory { site, .. } => site,
+            Diagnostic::UninitialisedRegister { site, .. } => site,
+        }
+    }
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let site = self.get_site();
+        write!(f, "At address {:04} [{:010}]: ", site.address, site.word)?;
+        match self {
+            Diagnostic::WriteToProtected { address, .. } => write!(f, "Wrote to the write-protected cell {:04}", address),
+            Diagnostic::SelfModifyingCode { address, .. } => write!(f, "Wrote to {:04}, which has already been executed", address),
+            Diagnostic::UninitialisedMemory { address, .. } => write!(f, "Read {:04}, which nothing has written yet", address),
+            Diagnostic::UninitialisedRegister { register, .. } => write!(f, "Read {}, which no instruction has written yet", register),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use drama_isa::word::{encode, encode_without_operand};
+    use drama_isa::{FunctionCode, Mode1, Mode2};
+
+    use super::*;
+    use crate::devices::TimerRegister;
+    use crate::io::ScriptedIo;
+    use crate::state::cpu::{RunOutcome, CPU};
+    use crate::state::ram::RAM;
+
+    fn run(report_uninitialised_reads: bool) -> Vec<Diagnostic> {
+        let r = |n| Register::new(n).unwrap();
+        let insn = |fc, mode1, acc, operand| encode(fc, mode1, Mode2::NoIndex, r(acc), Register::R0, operand);
+        let image = [
+            (0, insn(FunctionCode::HIA, Mode1::Direct, 1, 50)),
+            (1, insn(FunctionCode::OPT, Mode1::Value, 3, 1)),
+            (2, insn(FunctionCode::HIA, Mode1::Direct, 2, 60)),
+            (3, insn(FunctionCode::BIG, Mode1::Address, 2, 70)),
+            (4, insn(FunctionCode::HIA, Mode1::Direct, 4, 70)),
+            (5, insn(FunctionCode::HIA, Mode1::Direct, 5, 80)),
+            (6, insn(FunctionCode::OPT, Mode1::Value, 3, 1)),
+            (7, encode_without_operand(FunctionCode::STP)),
+            (60, 12),
+        ];
+        let mut ram = RAM::new();
+        ram.load_image(&image).unwrap();
+        ram.map(80, Box::new(TimerRegister::new())).unwrap();
+        let mut cpu = CPU::with_io(Box::new(ScriptedIo::default()));
+        cpu.report_uninitialised_reads = report_uninitialised_reads;
+        assert_eq!(cpu.run(&mut ram), Ok(RunOutcome::Halted));
+        cpu.diagnostics
+    }
+
+    #[test]
+    fn reads_of_cells_and_registers_nothing_wrote_are_reported() {
+        let diagnostics = run(true);
+        let sites: Vec<usize> = diagnostics.iter().map(|diagnostic| diagnostic.get_site().address).collect();
+        assert_eq!(sites, vec![0, 1]);
+        assert!(matches!(diagnostics[0], Diagnostic::UninitialisedMemory { address: 50, .. }));
+        assert!(matches!(diagnostics[1], Diagnostic::UninitialisedRegister { register, .. } if register == Register::new(3).unwrap()));
+        assert!(diagnostics[0].to_string().starts_with("At address 0000 ["));
+        assert!(diagnostics[0].to_string().ends_with("Read 0050, which nothing has written yet"));
+
+        assert!(run(false).is_empty());
+    }
+}
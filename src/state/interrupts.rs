@@ -0,0 +1,62 @@
+/// The number of interrupt lines, and so the number of entries in the vector table.
+pub const INTERRUPT_LINES: usize = 10;
+/// The line the timer raises.
+pub const TIMER_LINE: usize = 0;
+
+/// The state of the opt-in interrupt extension, see [`CPU::interrupts`](crate::state::cpu::CPU::interrupts).
+///
+/// When an interrupt is taken, the CPU pushes the instruction pointer on the `R9` stack as `SBR` does, then pushes
+/// the condition code (as `1`, `0` or `-1`), disables interrupts and jumps to the address in the vector table entry of
+/// the line. `KTO` pops both again and enables interrupts. Lower lines are taken first.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Interrupts {
+    /// Set by `OBA`, cleared by `OBU` and while a handler runs. Interrupts raised meanwhile stay pending.
+    pub enabled: bool,
+    /// The address of the handler for line `n` is stored at `vector_table + n`.
+    pub vector_table: usize,
+    /// Raises [`TIMER_LINE`] every this many instructions.
+    pub timer_interval: Option<u64>,
+    pending: [bool; INTERRUPT_LINES],
+}
+
+impl Interrupts {
+    /// Interrupts start out disabled, so a program can set up its handlers before executing `OBA`.
+    pub fn new(vector_table: usize) -> Self {
+        Interrupts {
+            enabled: false,
+            vector_table,
+            timer_interval: None,
+            pending: [false; INTERRUPT_LINES],
+        }
+    }
+
+    /// Marks `line` as pending. Lines past the last one are ignored.
+    pub fn raise(&mut self, line: usize) {
+        if line < INTERRUPT_LINES {
+            self.pending[line] = true;
+        }
+    }
+
+    pub fn is_pending(&self, line: usize) -> bool {
+        line < INTERRUPT_LINES && self.pending[line]
+    }
+
+    /// The line that would be taken before the next instruction.
+    pub(crate) fn next(&self) -> Option<usize> {
+        if !self.enabled {
+            return None;
+        }
+        self.pending.iter().position(|&pending| pending)
+    }
+
+    /// Disables interrupts and drops the pending ones, keeping the vector table and the timer.
+    pub(crate) fn reset(&mut self) {
+        self.enabled = false;
+        self.pending = [false; INTERRUPT_LINES];
+    }
+
+    pub(crate) fn acknowledge(&mut self, line: usize) {
+        self.pending[line] = false;
+        self.enabled = false;
+    }
+}
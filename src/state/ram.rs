@@ -1,16 +1,248 @@
+use std::fmt::Formatter;
 use std::ops::{Index, IndexMut};
 
+use drama_isa::{word, DecodeError, DecodedInstruction};
+
+use crate::devices::{MemoryMappedDevice, Xorshift};
+
 pub type RAM = RandomAccessMemory;
 
+/// The 10,000 memory cells, some of which may be taken over by [devices](MemoryMappedDevice).
+///
+/// Indexing always accesses the plain cells. The CPU reads and writes data through [`load`](RAM::load) and
+/// [`store`](RAM::store), which go to a device if one is mapped at the address.
 pub struct RandomAccessMemory {
-    inner: [isize; 10_000]
+    inner: [isize; 10_000],
+    /// The cells holding the program, as marked by the loader.
+    loaded: [bool; 10_000],
+    /// The cells the loader or the program has written.
+    initialised: [bool; 10_000],
+    write_protected: [bool; 10_000],
+    /// Whether every [executed](RAM::is_executed) cell counts as write-protected, see [`write_protect_code`](RAM::write_protect_code).
+    code_protected: bool,
+    /// The cells an instruction has been fetched from.
+    executed: [bool; 10_000],
+    /// Every cell that has been decoded since it was last written, with its decoding.
+    decoded: Vec<Option<DecodedInstruction>>,
+    mappings: Vec<Mapping>,
+}
+
+struct Mapping {
+    start: usize,
+    end: usize,
+    device: Box<dyn MemoryMappedDevice>,
 }
 
 impl RAM {
     pub fn new() -> Self {
         RAM {
-            inner: [0; 10_000]
+            inner: [0; 10_000],
+            loaded: [false; 10_000],
+            initialised: [false; 10_000],
+            write_protected: [false; 10_000],
+            code_protected: false,
+            executed: [false; 10_000],
+            decoded: vec![None; 10_000],
+            mappings: Vec::new(),
+        }
+    }
+
+    /// Sets every cell back to 0 and forgets what was loaded, initialised, write-protected and executed.
+    /// Devices stay mapped.
+    pub fn clear(&mut self) {
+        self.inner = [0; 10_000];
+        self.loaded = [false; 10_000];
+        self.initialised = [false; 10_000];
+        self.write_protected = [false; 10_000];
+        self.code_protected = false;
+        self.executed = [false; 10_000];
+        self.decoded.iter_mut().for_each(|decoded| *decoded = None);
+    }
+
+    /// Writes an assembled image, given as `(address, word)` pairs, and [marks](RAM::mark_loaded) its cells as loaded.
+    ///
+    /// Nothing is written if an address is outside of memory, appears twice, or is already loaded, or if a value
+    /// has more than ten digits. Loading several images side by side works as long as they don't overlap.
+    pub fn load_image(&mut self, image: &[(usize, isize)]) -> Result<(), ImageError> {
+        let mut taken = self.loaded;
+        for &(address, value) in image {
+            if address >= 10_000 {
+                return Err(ImageError::AddressOutOfRange(address));
+            }
+            if taken[address] {
+                return Err(ImageError::Overlap(address));
+            }
+            // Either the ten's complement reading of the digits or the digits themselves
+            if !(word::WORD_MIN..word::WORD_MODULUS).contains(&value) {
+                return Err(ImageError::InvalidWord { address, value });
+            }
+            taken[address] = true;
+        }
+        for &(address, value) in image {
+            self[address] = value;
+            self.mark_loaded(address);
+        }
+        Ok(())
+    }
+
+    /// Marks `address` as part of the program, code or assembled data, so the CPU can tell when the stack runs into it.
+    pub fn mark_loaded(&mut self, address: usize) {
+        self.loaded[address] = true;
+        self.initialised[address] = true;
+    }
+
+    pub fn is_loaded(&self, address: usize) -> bool {
+        self.loaded[address]
+    }
+
+    /// Marks `address` as holding a value that was put there on purpose, for cells set up without [`store`](RAM::store)
+    /// or [`mark_loaded`](RAM::mark_loaded).
+    pub fn mark_initialised(&mut self, address: usize) {
+        self.initialised[address] = true;
+    }
+
+    /// Sets whether `address` counts as [initialised](RAM::is_initialised), for undoing a store.
+    pub(crate) fn set_initialised(&mut self, address: usize, initialised: bool) {
+        self.initialised[address] = initialised;
+    }
+
+    /// Whether the loader or the program has written `address`. Writes through indexing don't count.
+    pub fn is_initialised(&self, address: usize) -> bool {
+        self.initialised[address]
+    }
+
+    /// Overwrites every cell that isn't initialised with a random word, so a program that counts on memory
+    /// starting out as zero behaves differently. The same seed gives the same garbage.
+    pub fn fill_with_garbage(&mut self, seed: u64) {
+        let mut random = Xorshift::new(seed);
+        for address in 0..10_000 {
+            let state = random.next();
+            if !self.initialised[address] {
+                self.inner[address] = (state % word::WORD_MODULUS as u64) as isize + word::WORD_MIN;
+                self.decoded[address] = None;
+            }
+        }
+    }
+
+    /// Makes the CPU refuse or report stores to `address`, depending on its [`WriteProtection`](crate::state::cpu::WriteProtection).
+    pub fn write_protect(&mut self, address: usize) {
+        self.write_protected[address] = true;
+    }
+
+    /// Write-protects every cell an instruction has been or will be fetched from.
+    ///
+    /// Code is told apart from data by being [executed](RAM::is_executed), so data stays writable whatever it
+    /// holds, and an instruction is only protected from the moment it first runs.
+    pub fn write_protect_code(&mut self) {
+        self.code_protected = true;
+    }
+
+    pub fn is_write_protected(&self, address: usize) -> bool {
+        self.write_protected[address] || (self.code_protected && self.executed[address])
+    }
+
+    pub fn mark_executed(&mut self, address: usize) {
+        self.executed[address] = true;
+    }
+
+    /// Sets whether `address` counts as [executed](RAM::is_executed), for undoing a fetch.
+    pub(crate) fn set_executed(&mut self, address: usize, executed: bool) {
+        self.executed[address] = executed;
+    }
+
+    /// Whether an instruction has ever been fetched from `address`.
+    pub fn is_executed(&self, address: usize) -> bool {
+        self.executed[address]
+    }
+
+    /// Decodes the cell at `address`, reusing the decoding from an earlier fetch if the cell hasn't been written since.
+    pub fn decode(&mut self, address: usize) -> Result<DecodedInstruction, DecodeError> {
+        if let Some(insn) = self.decoded[address] {
+            return Ok(insn);
+        }
+        let insn = DecodedInstruction::decode(self.inner[address])?;
+        self.decoded[address] = Some(insn);
+        Ok(insn)
+    }
+
+    /// Hands the addresses from `start` on to `device`, for as many addresses as it occupies.
+    pub fn map(&mut self, start: usize, device: Box<dyn MemoryMappedDevice>) -> Result<(), MappingError> {
+        let end = start + device.size();
+        if end > 10_000 {
+            return Err(MappingError::OutOfRange { start, end });
+        }
+        if let Some(mapping) = self.mappings.iter().find(|mapping| start < mapping.end && mapping.start < end) {
+            return Err(MappingError::Overlap { start, existing_start: mapping.start });
+        }
+        self.mappings.push(Mapping { start, end, device });
+        Ok(())
+    }
+
+    /// Removes all devices, leaving the plain cells underneath.
+    pub fn unmap_all(&mut self) {
+        self.mappings.clear();
+    }
+
+    /// Reads a cell the way an instruction does: from the device mapped there, if any.
+    pub fn load(&mut self, address: usize) -> isize {
+        match self.mappings.iter_mut().find(|mapping| mapping.start <= address && address < mapping.end) {
+            Some(mapping) => mapping.device.load(address - mapping.start),
+            None => self.inner[address],
+        }
+    }
+
+    /// Writes a cell the way an instruction does: to the device mapped there, if any.
+    pub fn store(&mut self, address: usize, value: isize) {
+        match self.mappings.iter_mut().find(|mapping| mapping.start <= address && address < mapping.end) {
+            Some(mapping) => mapping.device.store(address - mapping.start, value),
+            None => {
+                self.inner[address] = value;
+                self.decoded[address] = None;
+            }
         }
+        self.initialised[address] = true;
+    }
+
+    /// Whether a device is mapped at `address`.
+    pub fn is_mapped(&self, address: usize) -> bool {
+        self.mappings.iter().any(|mapping| mapping.start <= address && address < mapping.end)
+    }
+
+    /// Lets every device know an instruction has been executed.
+    pub fn tick(&mut self) {
+        for mapping in self.mappings.iter_mut() {
+            mapping.device.tick();
+        }
+    }
+
+    /// Collects the interrupts the devices want to raise.
+    pub fn poll_interrupts<F: FnMut(usize)>(&mut self, mut raise: F) {
+        for mapping in self.mappings.iter_mut() {
+            if let Some(line) = mapping.device.take_interrupt() {
+                raise(line);
+            }
+        }
+    }
+
+    /// Reads the zero-terminated string starting at `address` the way `DRS` does, [loading](RAM::load) every
+    /// character and wrapping around the end of memory. Words that aren't a character are read as U+FFFD.
+    /// Returns `None` if memory holds no terminator at all.
+    pub fn read_string(&mut self, address: isize) -> Option<String> {
+        let mut string = String::new();
+        for offset in 0..10_000 {
+            let value = self.load(self::address(address + offset));
+            if value == 0 {
+                return Some(string);
+            }
+            string.push(word::decode_char(value).unwrap_or(std::char::REPLACEMENT_CHARACTER));
+        }
+        None
+    }
+}
+
+impl Default for RAM {
+    fn default() -> Self {
+        RAM::new()
     }
 }
 
@@ -32,13 +264,16 @@ impl Index<isize> for RAM {
 
 impl IndexMut<usize> for RAM {
     fn index_mut(&mut self, index: usize) -> &mut Self::Output {
+        self.decoded[index] = None;
         &mut self.inner[index]
     }
 }
 
 impl IndexMut<isize> for RAM {
     fn index_mut(&mut self, index: isize) -> &mut Self::Output {
-        &mut self.inner[address(index)]
+        let index = address(index);
+        self.decoded[index] = None;
+        &mut self.inner[index]
     }
 }
 
@@ -49,4 +284,148 @@ pub fn address(number: isize) -> usize {
 
 pub fn expand(a: usize) -> isize {
     if a >= 5_000 { a as isize - 10_000 } else { a as isize }
-}
\ No newline at end of file
+}
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MappingError {
+    OutOfRange { start: usize, end: usize },
+    Overlap { start: usize, existing_start: usize },
+}
+
+impl std::error::Error for MappingError {}
+
+impl std::fmt::Display for MappingError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MappingError::OutOfRange { start, end } => write!(f, "A device at {}..{} does not fit in memory", start, end),
+            MappingError::Overlap { start, existing_start } => write!(f, "A device at {} overlaps the device at {}", start, existing_start),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageError {
+    AddressOutOfRange(usize),
+    /// The address appears twice in the image, or was already loaded.
+    Overlap(usize),
+    InvalidWord { address: usize, value: isize },
+}
+
+impl std::error::Error for ImageError {}
+
+impl std::fmt::Display for ImageError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImageError::AddressOutOfRange(address) => write!(f, "Address {} is outside of memory", address),
+            ImageError::Overlap(address) => write!(f, "Address {:04} is loaded twice", address),
+            ImageError::InvalidWord { address, value } => write!(f, "{} at address {:04} has more than ten digits", value, address),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use drama_isa::word::{encode, encode_without_operand};
+    use drama_isa::{Condition, FunctionCode, Mode1, Mode2, Register};
+
+    use super::*;
+    use crate::io::ScriptedIo;
+    use crate::state::cpu::{RunOutcome, WriteProtection, CPU};
+    use crate::state::cpu_fault::{CpuFault, FaultSite};
+
+    fn insn(fc: FunctionCode, mode1: Mode1, acc: Register, operand: isize) -> isize {
+        encode(fc, mode1, Mode2::NoIndex, acc, Register::R0, operand)
+    }
+
+    /// Runs a loop that overwrites its own body after the first time around, so a stale decoding shows.
+    fn run_self_modifying(decode_cache: bool) -> (CPU, RAM) {
+        let r = |n| Register::new(n).unwrap();
+        let program = [
+            insn(FunctionCode::HIA, Mode1::Direct, r(3), 10),
+            insn(FunctionCode::HIA, Mode1::Value, r(1), 2),
+            insn(FunctionCode::OPT, Mode1::Value, r(2), 1),
+            insn(FunctionCode::BIG, Mode1::Address, r(3), 2),
+            insn(FunctionCode::AFT, Mode1::Value, r(1), 1),
+            insn(FunctionCode::VGL, Mode1::Value, r(1), 0),
+            insn(FunctionCode::VSP, Mode1::Address, r(Condition::NNUL as usize), 2),
+            encode_without_operand(FunctionCode::STP),
+        ];
+        let mut image: Vec<(usize, isize)> = program.iter().copied().enumerate().collect();
+        image.push((10, insn(FunctionCode::OPT, Mode1::Value, r(2), 10)));
+        let mut ram = RAM::new();
+        ram.load_image(&image).unwrap();
+        let mut cpu = CPU::with_io(Box::new(ScriptedIo::default()));
+        cpu.decode_cache = decode_cache;
+        assert_eq!(cpu.run(&mut ram), Ok(RunOutcome::Halted));
+        (cpu, ram)
+    }
+
+    #[test]
+    fn the_decode_cache_does_not_change_what_a_program_does() {
+        let (cached_cpu, cached_ram) = run_self_modifying(true);
+        let (cpu, ram) = run_self_modifying(false);
+        assert_eq!(cpu.accumulators[2], 11);
+        assert_eq!(cached_cpu.accumulators, cpu.accumulators);
+        assert_eq!(cached_cpu.instruction_pointer, cpu.instruction_pointer);
+        assert_eq!(cached_cpu.cycles, cpu.cycles);
+        assert_eq!(cached_ram.inner[..], ram.inner[..]);
+    }
+
+    #[test]
+    fn writes_invalidate_the_decode_cache() {
+        let stp = encode_without_operand(FunctionCode::STP);
+        let nwl = encode_without_operand(FunctionCode::NWL);
+        let decodes_as = |ram: &mut RAM, address: usize| ram.decode(address).map(|insn| insn.fc).ok();
+
+        let mut ram = RAM::new();
+        ram.store(5, stp);
+        assert_eq!(decodes_as(&mut ram, 5), Some(FunctionCode::STP));
+        ram.store(5, nwl);
+        assert_eq!(decodes_as(&mut ram, 5), Some(FunctionCode::NWL));
+
+        ram[5usize] = stp;
+        assert_eq!(decodes_as(&mut ram, 5), Some(FunctionCode::STP));
+        ram[5isize] = nwl;
+        assert_eq!(decodes_as(&mut ram, 5), Some(FunctionCode::NWL));
+
+        ram.clear();
+        assert_eq!(decodes_as(&mut ram, 5), None);
+
+        // Indexing doesn't initialise the cell, so the garbage goes over it
+        ram[5usize] = stp;
+        assert_eq!(decodes_as(&mut ram, 5), Some(FunctionCode::STP));
+        ram.fill_with_garbage(1);
+        assert_ne!(ram[5usize], stp);
+        assert_eq!(ram.decode(5), DecodedInstruction::decode(ram[5usize]));
+    }
+
+    #[test]
+    fn only_executed_cells_are_protected_as_code() {
+        let r1 = Register::new(1).unwrap();
+        // Data that happens to be a valid instruction
+        let data = insn(FunctionCode::HIA, Mode1::Value, r1, 7);
+        let image = [
+            (0, insn(FunctionCode::HIA, Mode1::Value, r1, 0)),
+            (1, insn(FunctionCode::BIG, Mode1::Address, r1, 6)),
+            (2, insn(FunctionCode::BIG, Mode1::Address, r1, 0)),
+            (3, encode_without_operand(FunctionCode::STP)),
+            (6, data),
+        ];
+        let mut ram = RAM::new();
+        ram.load_image(&image).unwrap();
+        ram.write_protect_code();
+        let mut cpu = CPU::with_io(Box::new(ScriptedIo::default()));
+        cpu.write_protection = WriteProtection::Fault;
+
+        cpu.step(&mut ram).unwrap();
+        cpu.step(&mut ram).unwrap();
+        assert_eq!(ram[6usize], 0);
+        assert!(!ram.is_write_protected(6));
+        assert!(matches!(cpu.step(&mut ram), Err(CpuFault::WriteToProtected(FaultSite { address: 2, .. }, 0))));
+        assert_eq!(ram[0usize], image[0].1);
+        assert!(ram.is_write_protected(0) && !ram.is_write_protected(3));
+
+        ram.clear();
+        ram.mark_executed(0);
+        assert!(!ram.is_write_protected(0));
+    }
+}
@@ -0,0 +1,110 @@
+//! The behaviours on which simulators of DRAMA disagree, each of which can be switched on its own.
+
+/// How the CPU behaves where the specification leaves room, as set in [`CPU::semantics`](crate::state::cpu::CPU::semantics).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SemanticsProfile {
+    /// What `OPT`, `AFT`, `VER`, `DEL` and `MOD` set the condition code from.
+    pub condition_code: ConditionCodeSource,
+    /// How `DEL` and `MOD` round when the signs of their operands differ.
+    pub division: Division,
+    /// What happens to an address outside of memory.
+    pub addresses: AddressRange,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConditionCodeSource {
+    /// The operand, as it was before the accumulator changed.
+    Operand,
+    /// The new value of the accumulator.
+    Result,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Division {
+    /// Round the quotient towards zero, so the remainder has the sign of the dividend: `-7 DEL 2` is `-3`, `-7 MOD 2` is `-1`.
+    Truncate,
+    /// Round the quotient down, so the remainder has the sign of the divisor: `-7 DEL 2` is `-4`, `-7 MOD 2` is `1`.
+    Floor,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AddressRange {
+    /// Keep the last four digits, so `10005` is `0005` and `-1` is `9999`.
+    Wrap,
+    /// Stop with [`CpuFault::AddressOutOfRange`](crate::state::cpu_fault::CpuFault::AddressOutOfRange) unless the
+    /// address is what a four digit operand can spell: `0` to `9999`, or `-5000` to `-1` for `5000` to `9999`.
+    Fault,
+}
+
+impl SemanticsProfile {
+    /// What the course's reference simulator does.
+    pub const REFERENCE_COMPATIBLE: SemanticsProfile = SemanticsProfile {
+        condition_code: ConditionCodeSource::Result,
+        division: Division::Floor,
+        addresses: AddressRange::Fault,
+    };
+
+    /// What this simulator has always done, and does by default.
+    pub const THIS_PROJECT: SemanticsProfile = SemanticsProfile {
+        condition_code: ConditionCodeSource::Operand,
+        division: Division::Truncate,
+        addresses: AddressRange::Wrap,
+    };
+
+    /// Finds a profile by its name, `reference-compatible` or `this-project`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "reference-compatible" => Some(SemanticsProfile::REFERENCE_COMPATIBLE),
+            "this-project" => Some(SemanticsProfile::THIS_PROJECT),
+            _ => None,
+        }
+    }
+
+    /// The name of the profile, if these are exactly the settings of one.
+    pub fn name(&self) -> Option<&'static str> {
+        if *self == SemanticsProfile::REFERENCE_COMPATIBLE {
+            Some("reference-compatible")
+        } else if *self == SemanticsProfile::THIS_PROJECT {
+            Some("this-project")
+        } else {
+            None
+        }
+    }
+
+    /// Divides as [`division`](SemanticsProfile::division) says, returning the quotient and the remainder.
+    /// The divisor must not be zero.
+    pub fn divide(&self, dividend: i128, divisor: i128) -> (i128, i128) {
+        let (quotient, remainder) = (dividend / divisor, dividend % divisor);
+        match self.division {
+            Division::Floor if remainder != 0 && (remainder < 0) != (divisor < 0) => (quotient - 1, remainder + divisor),
+            _ => (quotient, remainder),
+        }
+    }
+}
+
+impl Default for SemanticsProfile {
+    fn default() -> Self {
+        SemanticsProfile::THIS_PROJECT
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_profile_rounds_division_its_own_way() {
+        // dividend, divisor, truncated (quotient, remainder), floored (quotient, remainder)
+        let cases = [
+            (7, 2, (3, 1), (3, 1)),
+            (-7, 2, (-3, -1), (-4, 1)),
+            (7, -2, (-3, 1), (-4, -1)),
+            (-7, -2, (3, -1), (3, -1)),
+            (-6, 2, (-3, 0), (-3, 0)),
+        ];
+        for (dividend, divisor, truncated, floored) in cases {
+            assert_eq!(SemanticsProfile::THIS_PROJECT.divide(dividend, divisor), truncated, "{} / {}", dividend, divisor);
+            assert_eq!(SemanticsProfile::REFERENCE_COMPATIBLE.divide(dividend, divisor), floored, "{} / {}", dividend, divisor);
+        }
+    }
+}
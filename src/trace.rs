@@ -0,0 +1,173 @@
+//! Writes every executed instruction to a file, for diffing runs and for analysis by other tools.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use drama_isa::disassembler::disassemble_instruction;
+
+use crate::state::cpu::StepOutcome;
+
+/// The layout of a trace. Both hold one executed instruction per line, written out as dasm would write it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraceFormat {
+    /// One JSON object per line.
+    JsonLines,
+    /// Comma separated, with a header line. The deltas are `;`-separated lists in a single column each.
+    Csv,
+}
+
+impl TraceFormat {
+    /// Picks the format from a file extension: `.csv` is CSV, anything else JSON Lines.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some(extension) if extension.eq_ignore_ascii_case("csv") => TraceFormat::Csv,
+            _ => TraceFormat::JsonLines,
+        }
+    }
+}
+
+/// Records instructions to a writer, as set in [`CPU::trace`](crate::state::cpu::CPU::trace).
+///
+/// A write error doesn't interrupt the program. The trace stops at the first error, which [`finish`](TraceWriter::finish) reports.
+pub struct TraceWriter {
+    writer: Box<dyn Write>,
+    format: TraceFormat,
+    error: Option<io::Error>,
+}
+
+impl TraceWriter {
+    pub fn new(writer: Box<dyn Write>, format: TraceFormat) -> Self {
+        let mut trace = TraceWriter { writer, format, error: None };
+        if format == TraceFormat::Csv {
+            let result = writeln!(trace.writer, "step,ip,word,mnemonic,operand,registers,memory,condition_code");
+            trace.error = result.err();
+        }
+        trace
+    }
+
+    /// Creates the file at `path`, choosing the format from its extension.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let file = File::create(path)?;
+        Ok(TraceWriter::new(Box::new(BufWriter::new(file)), TraceFormat::from_path(path)))
+    }
+
+    /// Writes the line for an instruction. `step` counts the instructions executed so far, this one included.
+    pub fn record(&mut self, step: u64, outcome: &StepOutcome) {
+        if self.error.is_some() {
+            return;
+        }
+        let line = match self.format {
+            TraceFormat::JsonLines => json_line(step, outcome),
+            TraceFormat::Csv => csv_line(step, outcome),
+        };
+        if let Err(error) = writeln!(self.writer, "{}", line) {
+            self.error = Some(error);
+        }
+    }
+
+    /// Flushes the trace, returning the first error that occurred while writing it.
+    pub fn finish(mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(error) => Err(error),
+            None => self.writer.flush(),
+        }
+    }
+}
+
+fn json_line(step: u64, outcome: &StepOutcome) -> String {
+    let registers: Vec<String> = outcome.register_changes.iter()
+        .map(|change| format!("{{\"register\":\"{}\",\"old\":{},\"new\":{}}}", change.register, change.old, change.new))
+        .collect();
+    let memory: Vec<String> = outcome.memory_changes.iter()
+        .map(|change| format!("{{\"address\":{},\"old\":{},\"new\":{}}}", change.address, change.old, change.new))
+        .collect();
+    let condition_code = match outcome.condition_code_change {
+        Some((old, new)) => format!("{{\"old\":\"{}\",\"new\":\"{}\"}}", old.mnemonic(), new.mnemonic()),
+        None => "null".to_string(),
+    };
+    format!(
+        "{{\"step\":{},\"ip\":{},\"word\":{},\"mnemonic\":\"{}\",\"operand\":{},\"registers\":[{}],\"memory\":[{}],\"condition_code\":{}}}",
+        step, outcome.address, outcome.word, disassemble_instruction(&outcome.instruction), outcome.operand,
+        registers.join(","), memory.join(","), condition_code
+    )
+}
+
+fn csv_line(step: u64, outcome: &StepOutcome) -> String {
+    let registers: Vec<String> = outcome.register_changes.iter()
+        .map(|change| format!("{}:{}->{}", change.register, change.old, change.new))
+        .collect();
+    let memory: Vec<String> = outcome.memory_changes.iter()
+        .map(|change| format!("{}:{}->{}", change.address, change.old, change.new))
+        .collect();
+    let condition_code = match outcome.condition_code_change {
+        Some((old, new)) => format!("{}->{}", old.mnemonic(), new.mnemonic()),
+        None => String::new(),
+    };
+    // An instruction with operands has a comma in it, and never a quote
+    format!(
+        "{},{},{},\"{}\",{},{},{},{}",
+        step, outcome.address, outcome.word, disassemble_instruction(&outcome.instruction), outcome.operand,
+        registers.join(";"), memory.join(";"), condition_code
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use drama_isa::word::{encode, encode_without_operand};
+    use drama_isa::{FunctionCode, Mode1, Mode2, Register};
+
+    use super::*;
+    use crate::io::ScriptedIo;
+    use crate::state::cpu::{RunOutcome, CPU};
+    use crate::state::ram::RAM;
+
+    /// A writer whose contents can still be read after it was handed to the trace.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn a_csv_trace_holds_every_instruction_in_full() {
+        let r1 = Register::new(1).unwrap();
+        let program = [
+            encode(FunctionCode::HIA, Mode1::Value, Mode2::NoIndex, r1, Register::R0, 5),
+            encode(FunctionCode::BIG, Mode1::Address, Mode2::NoIndex, r1, Register::R0, 10),
+            encode(FunctionCode::SPR, Mode1::Address, Mode2::NoIndex, Register::R0, Register::R0, 3),
+            encode_without_operand(FunctionCode::STP),
+        ];
+        let mut ram = RAM::new();
+        ram.load_image(&program.iter().copied().enumerate().collect::<Vec<_>>()).unwrap();
+        let buffer = SharedBuffer::default();
+        let mut cpu = CPU::with_io(Box::new(ScriptedIo::default()));
+        cpu.trace = Some(TraceWriter::new(Box::new(buffer.clone()), TraceFormat::Csv));
+        assert_eq!(cpu.run(&mut ram), Ok(RunOutcome::Halted));
+        cpu.trace.take().unwrap().finish().unwrap();
+
+        let csv = String::from_utf8(buffer.0.borrow().clone()).unwrap();
+        let expected = [
+            "step,ip,word,mnemonic,operand,registers,memory,condition_code".to_string(),
+            format!("1,0,{},\"HIA.w R1, 5\",5,R1:0->5,,EQL->POS", program[0]),
+            format!("2,1,{},\"BIG R1, 10\",10,,10:0->5,", program[1]),
+            format!("3,2,{},\"SPR 3\",3,,,", program[2]),
+            format!("4,3,{},\"STP\",0,,,", program[3]),
+        ];
+        for (line, expected) in csv.lines().zip(expected.iter()) {
+            assert_eq!(line, expected);
+        }
+        assert_eq!(csv.lines().count(), expected.len());
+    }
+}
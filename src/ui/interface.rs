@@ -85,5 +85,6 @@ pub fn gui() {
 
         win.show_all();
     });
-    uiapp.run(&env::args().collect::<Vec<_>>());
+    // Only the program name: GApplication would take `gui` for a file to open
+    uiapp.run(&env::args().take(1).collect::<Vec<_>>());
 }